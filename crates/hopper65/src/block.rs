@@ -1,30 +1,146 @@
 use super::*;
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub struct State<V> {
-    pub regmap: BTreeMap<V, (Reg, u32)>,
-    pub insts: Vec<Inst>,
+pub struct State<V, R: 'static = Reg> {
+    /// Where each value lives: its register and the instruction index just
+    /// past its definition, from which on `sets_at` checks for clobbers.
+    pub regmap: BTreeMap<V, (R, u32)>,
+    pub insts: Vec<Inst<R>>,
+    /// The comparison the flags were last set by.
+    pub flags: Option<Flags<V>>,
+    /// Whether the decimal flag is set at the end of the block so far.
+    pub decimal: bool,
 }
+impl<V, R> Default for State<V, R> {
+    fn default() -> Self {
+        Self {
+            regmap: BTreeMap::new(),
+            insts: Vec::new(),
+            flags: None,
+            decimal: false,
+        }
+    }
+}
+/// The result of a comparison held in the flags.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub enum Inst {
-    StoreArg { reg: Reg, fwd: u32 },
-    LoadConst { reg: Reg, value: u8 },
-    Transfer { from: Reg, to: Reg },
+pub struct Flags<V> {
+    pub value: V,
+    /// Instruction index just past the compare.
+    pub at: u32,
+    /// Whether the operands were compared the other way around.
+    pub swapped: bool,
+}
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Inst<R: 'static = Reg> {
+    StoreArg {
+        reg: R,
+        fwd: u32,
+    },
+    LoadConst {
+        reg: R,
+        value: u8,
+    },
+    Transfer {
+        from: R,
+        to: R,
+    },
+    Exchange {
+        swaps: &'static [(R, R)],
+    },
+    /// A padding instruction taking `cycles` cycles, one of `RegFile::PADS`.
+    Pad {
+        cycles: u32,
+    },
+    Compare {
+        reg: R,
+        with: Operand<R>,
+    },
+    Branch {
+        cond: Cond,
+        target: u32,
+    },
+    /// Add or subtract into the accumulator.
+    Arith {
+        op: ArithOp,
+        with: Operand<R>,
+    },
+    /// Set or clear the carry, as `SEC` and `CLC` on the 6502.
+    SetCarry(bool),
+    /// Set or clear the decimal flag, as `SED` and `CLD` on the 6502.
+    SetDecimal(bool),
+    /// Adjust the accumulator to BCD after an add or subtract, as Z80 `DAA`.
+    DecimalAdjust,
+    Call {
+        target: u16,
+    },
+}
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ArithOp {
+    Add,
+    Sub,
+}
+/// The second operand of an instruction.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Operand<R> {
+    Reg(R),
+    Imm(u8),
+    Abs(u16),
+}
+/// A branch condition after a compare, as `BEQ`, `BNE`, `BCC`, `BCS`,
+/// `BMI` and `BPL` on the 6502.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Cond {
+    Eq,
+    Ne,
+    /// Unsigned less than.
+    Lt,
+    /// Unsigned greater than or equal.
+    Ge,
+    Mi,
+    Pl,
+}
+/// The definition index and register an immediate operand is patched from.
+type Patch<R> = Option<(u32, R)>;
+/// Where an op's second operand comes from.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Source<V> {
+    Value(V),
+    Const(u8),
+    Mem(u16),
 }
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Op<V> {
     Just(V),
     Const(u8),
+    /// Compare a value against a source, leaving the result in the flags.
+    Compare(V, Source<V>),
+    /// Add a source to a value, both packed BCD.
+    DecimalAdd(V, Source<V>),
+    /// Subtract a source from a value, both packed BCD.
+    DecimalSub(V, Source<V>),
 }
-impl<V> State<V> {
-    pub fn add_patch(&mut self, orig: u32, reg: Reg, target: Reg) {
+impl<V, R: RegFile> State<V, R> {
+    pub fn add_patch(&mut self, orig: u32, reg: R, target: R) {
+        self.patch_into(
+            orig,
+            reg,
+            Inst::LoadConst {
+                reg: target,
+                value: 0u8,
+            },
+        );
+    }
+    /// Append `inst`, storing `reg` at `orig` into its immediate operand.
+    pub fn patch_into(&mut self, orig: u32, reg: R, inst: Inst<R>) {
         let l = self.insts.len() as u32 + 1 - orig;
         self.insts
             .insert(orig as usize, Inst::StoreArg { reg, fwd: l });
-        self.insts.push(Inst::LoadConst {
-            reg: target,
-            value: 0u8,
-        });
+        self.insts.push(inst);
+        if let Some(f) = &mut self.flags
+            && f.at >= orig
+        {
+            f.at += 1;
+        }
         for i in self.insts[..(orig as usize)].iter_mut() {
             if let Inst::StoreArg { fwd, .. } = i {
                 *fwd += 1;
@@ -36,7 +152,7 @@ impl<V> State<V> {
             }
         }
     }
-    pub fn sets_at(&self, lim: u32, reg: Reg) -> bool {
+    pub fn sets_at(&self, lim: u32, reg: R) -> bool {
         let mut idx = self.insts.len() as u32;
         loop {
             if idx == lim {
@@ -50,68 +166,575 @@ impl<V> State<V> {
                 Inst::Transfer { from, to } if *to == reg && *from != reg => {
                     return true;
                 }
+                Inst::Exchange { swaps } if swaps.iter().any(|(a, b)| *a == reg || *b == reg) => {
+                    return true;
+                }
+                Inst::StoreArg { reg: r, .. } if r.store_clobbers().contains(&reg) => {
+                    return true;
+                }
+                Inst::Arith { .. } | Inst::DecimalAdjust if reg == R::ACC => {
+                    return true;
+                }
+                // a subroutine may change any register
+                Inst::Call { .. } => {
+                    return true;
+                }
                 _ => {}
             }
         }
     }
-    fn get_into_a(&mut self, this: V)
+    /// Whether `inst` needs the value held in `reg`.
+    fn reads(inst: &Inst<R>, reg: R) -> bool {
+        match inst {
+            Inst::StoreArg { reg: r, .. } | Inst::Transfer { from: r, .. } => *r == reg,
+            Inst::Exchange { swaps } => swaps.iter().any(|&(a, b)| a == reg || b == reg),
+            Inst::Compare { reg: r, with } => *r == reg || *with == Operand::Reg(reg),
+            Inst::Arith { with, .. } => reg == R::ACC || *with == Operand::Reg(reg),
+            Inst::DecimalAdjust => reg == R::ACC,
+            // a subroutine may take arguments in any register
+            Inst::Call { .. } => true,
+            _ => false,
+        }
+    }
+    /// Whether `reg` can be stored at `orig` to patch a later operand,
+    /// without overwriting a register read after that point.
+    fn can_patch(&self, orig: u32, reg: R) -> bool {
+        reg.can_store()
+            && reg.store_clobbers().iter().all(|&c| {
+                !self.insts[orig as usize..]
+                    .iter()
+                    .any(|inst| Self::reads(inst, c))
+            })
+    }
+    /// Copy the value defined into `or` at `oi` into `r`, with a transfer
+    /// while `or` still holds it and by patching it into a later immediate
+    /// load once `or` has been clobbered.
+    fn copy_into(&self, this: &V, or: R, oi: u32, r: R) -> Option<Self>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let mut new = self.clone();
+        if new.sets_at(oi, or) || !R::can_transfer(or, r) {
+            if !new.can_patch(oi, or) || !r.can_load() {
+                return None;
+            }
+            new.add_patch(oi, or, r);
+        } else {
+            new.insts.push(Inst::Transfer { from: or, to: r });
+        }
+        new.regmap.insert(this.clone(), (r, new.insts.len() as u32));
+        Some(new)
+    }
+    /// Whether the flags are changed from instruction index `lim` on.
+    pub fn sets_flags_at(&self, lim: u32) -> bool {
+        self.insts[lim as usize..].iter().any(R::sets_flags)
+    }
+    fn flags_live(&self) -> bool {
+        self.flags
+            .as_ref()
+            .is_some_and(|f| !self.sets_flags_at(f.at))
+    }
+    /// The ways `rhs` can be supplied as an operand, each with the
+    /// definition to patch it in from when it is passed as an immediate.
+    fn operands(&self, rhs: &Source<V>) -> Vec<(Operand<R>, Patch<R>)>
+    where
+        V: Ord,
+    {
+        let mut cands = Vec::new();
+        match rhs {
+            Source::Const(c) => cands.push((Operand::Imm(*c), None)),
+            Source::Mem(a) => cands.push((Operand::Abs(*a), None)),
+            Source::Value(v) => {
+                if let Some(&(or, oi)) = self.regmap.get(v) {
+                    if !self.sets_at(oi, or) {
+                        cands.push((Operand::Reg(or), None));
+                    }
+                    if self.can_patch(oi, or) {
+                        cands.push((Operand::Imm(0), Some((oi, or))));
+                    }
+                }
+            }
+        }
+        cands
+    }
+    /// Append `inst`, patching its operand in if need be. False, leaving
+    /// the state as it was, if the store would overwrite a register `inst`
+    /// reads.
+    fn emit(&mut self, inst: Inst<R>, patch: Patch<R>) -> bool {
+        match patch {
+            Some((_, or)) if or.store_clobbers().iter().any(|&c| Self::reads(&inst, c)) => {
+                return false;
+            }
+            Some((oi, or)) => self.patch_into(oi, or, inst),
+            None => self.insts.push(inst),
+        }
+        true
+    }
+    /// Compare candidates with `lhs` in a register, collected into `out`.
+    fn compare(
+        &self,
+        out: &mut BTreeSet<State<V, R>>,
+        this: &V,
+        lhs: &V,
+        rhs: &Source<V>,
+        swapped: bool,
+    ) where
+        V: Clone + core::cmp::Ord,
+    {
+        for &r in R::ALL {
+            for s in self.into_reg(lhs, r) {
+                for (with, patch) in s.operands(rhs) {
+                    if !R::can_compare(r, &with) {
+                        continue;
+                    }
+                    let mut new = s.clone();
+                    if !new.emit(Inst::Compare { reg: r, with }, patch) {
+                        continue;
+                    }
+                    new.flags = Some(Flags {
+                        value: this.clone(),
+                        at: new.insts.len() as u32,
+                        swapped,
+                    });
+                    out.insert(new);
+                }
+            }
+        }
+    }
+    /// Decimal add or subtract candidates with `lhs` in the accumulator,
+    /// entering decimal mode first if the block is not already in it.
+    fn decimal(&self, this: &V, lhs: &V, rhs: &Source<V>, op: ArithOp) -> BTreeSet<State<V, R>>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let mut out = BTreeSet::new();
+        for s in self.into_reg(lhs, R::ACC) {
+            for (with, patch) in s.operands(rhs) {
+                if !R::can_arith(&with) {
+                    continue;
+                }
+                let mut new = s.clone();
+                if R::DECIMAL_FLAG && !new.decimal {
+                    new.insts.push(Inst::SetDecimal(true));
+                    new.decimal = true;
+                }
+                if R::CARRY_IN {
+                    // ADC adds the carry in, SBC subtracts its complement
+                    new.insts.push(Inst::SetCarry(op == ArithOp::Sub));
+                }
+                if !new.emit(Inst::Arith { op, with }, patch) {
+                    continue;
+                }
+                if !R::DECIMAL_FLAG {
+                    new.insts.push(Inst::DecimalAdjust);
+                }
+                new.regmap
+                    .insert(this.clone(), (R::ACC, new.insts.len() as u32));
+                out.insert(new);
+            }
+        }
+        out
+    }
+    /// Leave decimal mode if the block is in it.
+    fn leave_decimal(&self) -> Self
+    where
+        V: Clone,
+    {
+        let mut new = self.clone();
+        if new.decimal {
+            new.insts.push(Inst::SetDecimal(false));
+            new.decimal = false;
+        }
+        new
+    }
+    /// End the block by falling through, out of decimal mode.
+    pub fn finish(&self) -> Self
+    where
+        V: Clone,
+    {
+        self.leave_decimal()
+    }
+    /// Call a subroutine, out of decimal mode; every register and the
+    /// flags are assumed clobbered across it.
+    pub fn call(&self, target: u16) -> Self
+    where
+        V: Clone,
+    {
+        let mut new = self.leave_decimal();
+        new.insts.push(Inst::Call { target });
+        new
+    }
+    /// End the block with a branch to `target` on the comparison `flags`,
+    /// out of decimal mode.
+    ///
+    /// Empty if the flags no longer hold that comparison, or if it was made
+    /// with swapped operands and `cond` cannot be tested that way round.
+    pub fn branch(&self, flags: &V, cond: Cond, target: u32) -> BTreeSet<State<V, R>>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let Some(f) = &self.flags else {
+            return BTreeSet::new();
+        };
+        if f.value != *flags
+            || !self.flags_live()
+            || (f.swapped && !matches!(cond, Cond::Eq | Cond::Ne))
+        {
+            return BTreeSet::new();
+        }
+        let mut new = self.leave_decimal();
+        new.insts.push(Inst::Branch { cond, target });
+        [new].into_iter().collect()
+    }
+    /// Perform an exchange, moving every live value held in one of the
+    /// swapped registers to its partner.
+    pub fn exchange(&self, swaps: &'static [(R, R)]) -> Self
+    where
+        V: Clone,
+    {
+        let mut new = self.clone();
+        let idx = new.insts.len() as u32 + 1;
+        for (r, i) in new.regmap.values_mut() {
+            if self.sets_at(*i, *r) {
+                continue;
+            }
+            let partner = swaps.iter().find_map(|&(a, b)| {
+                if a == *r {
+                    Some(b)
+                } else if b == *r {
+                    Some(a)
+                } else {
+                    None
+                }
+            });
+            if let Some(p) = partner {
+                *r = p;
+                *i = idx;
+            }
+        }
+        new.insts.push(Inst::Exchange { swaps });
+        new
+    }
+    /// Candidate states in which the value `this` is held in `target`.
+    pub fn into_reg(&self, this: &V, target: R) -> BTreeSet<State<V, R>>
     where
         V: Clone + core::cmp::Ord,
     {
-        if let Some((or, oi)) = self.regmap.get(&this) {
-            if self.sets_at(*oi, Reg::A) {
-                self.add_patch(*oi, Reg::A, *or);
-            } else {
-                self.insts.push(Inst::Transfer {
-                    from: *or,
-                    to: Reg::A,
-                });
-                self.regmap
-                    .insert(this.clone(), (Reg::A, self.insts.len() as u32 - 1));
+        let Some((or, oi)) = self.regmap.get(this).cloned() else {
+            return BTreeSet::new();
+        };
+        let live = !self.sets_at(oi, or);
+        if or == target && live {
+            return [self.clone()].into_iter().collect();
+        }
+        let mut out: BTreeSet<_> = self.copy_into(this, or, oi, target).into_iter().collect();
+        if live {
+            for swaps in R::EXCHANGES {
+                if swaps
+                    .iter()
+                    .any(|&(a, b)| (a, b) == (or, target) || (b, a) == (or, target))
+                {
+                    out.insert(self.exchange(swaps));
+                }
             }
         }
+        out
     }
-    pub fn on(&self, this: V, op: Op<V>) -> BTreeSet<State<V>>
+    pub fn on(&self, this: V, op: Op<V>) -> BTreeSet<State<V, R>>
     where
         V: Clone + core::cmp::Ord,
     {
         let mut new = self.clone();
         match op {
             Op::Just(v) => {
-                if let Some((or, oi)) = new.regmap.get(&v) {
-                    if new.sets_at(*oi, *or) {
-                        [Reg::A, Reg::X, Reg::Y]
-                            .into_iter()
-                            .map(|r| {
-                                let mut new = new.clone();
-                                if new.sets_at(*oi, r) {
-                                    new.add_patch(*oi, *or, r);
-                                } else {
-                                    new.insts.push(Inst::Transfer { from: *or, to: r });
-                                }
-                                new.regmap
-                                    .insert(this.clone(), (r, new.insts.len() as u32 - 1));
-                                new
-                            })
+                if let Some((or, oi)) = new.regmap.get(&v).cloned() {
+                    if new.sets_at(oi, or) {
+                        R::ALL
+                            .iter()
+                            .filter_map(|&r| new.copy_into(&this, or, oi, r))
                             .collect::<BTreeSet<_>>()
                     } else {
-                        new.regmap.insert(this.clone(), (*or, *oi));
+                        new.regmap.insert(this.clone(), (or, oi));
                         [new].into_iter().collect()
                     }
                 } else {
                     [].into_iter().collect()
                 }
             }
-            Op::Const(a) => [Reg::A, Reg::X, Reg::Y]
-                .into_iter()
-                .map(|r| {
+            Op::Const(a) => R::ALL
+                .iter()
+                .filter(|r| r.can_load())
+                .map(|&r| {
                     let mut new = new.clone();
                     new.insts.push(Inst::LoadConst { reg: r, value: a });
-                    new.regmap
-                        .insert(this.clone(), (r, new.insts.len() as u32 - 1));
+                    new.regmap.insert(this.clone(), (r, new.insts.len() as u32));
                     new
                 })
                 .collect::<BTreeSet<_>>(),
+            Op::Compare(lhs, rhs) => {
+                let mut out = BTreeSet::new();
+                new.compare(&mut out, &this, &lhs, &rhs, false);
+                if let Source::Value(v) = &rhs {
+                    new.compare(&mut out, &this, v, &Source::Value(lhs.clone()), true);
+                }
+                out
+            }
+            Op::DecimalAdd(lhs, rhs) => new.decimal(&this, &lhs, &rhs, ArithOp::Add),
+            Op::DecimalSub(lhs, rhs) => new.decimal(&this, &lhs, &rhs, ArithOp::Sub),
+        }
+    }
+    /// Cycles taken by the block so far.
+    pub fn cycles(&self) -> u32 {
+        self.insts.iter().map(R::cycles).sum()
+    }
+    /// The padding instructions usable at the end of the block, none once a
+    /// branch has ended it, and the fewest of them summing to each cycle count
+    /// up to `max`, if reachable.
+    fn pad_table(&self, max: usize) -> (Vec<u32>, Vec<Option<u32>>) {
+        let pads: Vec<u32> = if matches!(self.insts.last(), Some(Inst::Branch { .. })) {
+            Vec::new()
+        } else {
+            let flags_live = self.flags_live();
+            R::PADS
+                .iter()
+                .copied()
+                .filter(|&p| !(flags_live && R::sets_flags(&Inst::Pad { cycles: p })))
+                .collect()
+        };
+        let mut fewest: Vec<Option<u32>> = alloc::vec![None; max + 1];
+        fewest[0] = Some(0);
+        for n in 1..=max {
+            fewest[n] = pads
+                .iter()
+                .filter_map(|&p| fewest[n.checked_sub(p as usize)?])
+                .min()
+                .map(|c| c + 1);
+        }
+        (pads, fewest)
+    }
+    /// Every way of padding the block by `deficit` cycles with the fewest
+    /// instructions, from a [`State::pad_table`] covering `deficit`.
+    fn padded(&self, deficit: usize, pads: &[u32], fewest: &[Option<u32>]) -> BTreeSet<State<V, R>>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let mut out = BTreeSet::new();
+        let mut stack = alloc::vec![(self.clone(), deficit, u32::MAX)];
+        while let Some((s, left, max)) = stack.pop() {
+            if left == 0 {
+                out.insert(s);
+                continue;
+            }
+            let Some(count) = fewest[left] else {
+                continue;
+            };
+            // pads are emitted in non-increasing order so each multiset appears once
+            for &p in pads.iter().filter(|&&p| p <= max) {
+                let Some(rest) = left.checked_sub(p as usize) else {
+                    continue;
+                };
+                if fewest[rest].map(|c| c + 1) != Some(count) {
+                    continue;
+                }
+                let mut new = s.clone();
+                new.insts.push(Inst::Pad { cycles: p });
+                stack.push((new, rest, p));
+            }
+        }
+        out
+    }
+    /// Candidate states padded so the block takes exactly `target` cycles,
+    /// using as few padding instructions as possible. A block ended by a
+    /// branch cannot be padded.
+    pub fn pad_to(&self, target: u32) -> BTreeSet<State<V, R>>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let Some(deficit) = target.checked_sub(self.cycles()) else {
+            return BTreeSet::new();
+        };
+        let (pads, fewest) = self.pad_table(deficit as usize);
+        self.padded(deficit as usize, &pads, &fewest)
+    }
+    /// Candidate states padded to the earliest cycle count in `window` that
+    /// can be met exactly; empty if the block already runs past it.
+    pub fn within(&self, window: RangeInclusive<u32>) -> BTreeSet<State<V, R>>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let base = self.cycles();
+        let Some(max) = window.end().checked_sub(base) else {
+            return BTreeSet::new();
+        };
+        let (pads, fewest) = self.pad_table(max as usize);
+        let lo = window.start().saturating_sub(base);
+        (lo..=max)
+            .find(|&d| fewest[d as usize].is_some())
+            .map(|d| self.padded(d as usize, &pads, &fewest))
+            .unwrap_or_default()
+    }
+    /// Like [`State::on`], but padding ahead of the op so that it completes
+    /// within `window` cycles of the block start.
+    pub fn on_within(
+        &self,
+        this: V,
+        op: Op<V>,
+        window: RangeInclusive<u32>,
+    ) -> BTreeSet<State<V, R>>
+    where
+        V: Clone + core::cmp::Ord,
+    {
+        let base = self.cycles();
+        let mut out = BTreeSet::new();
+        for done in self.on(this.clone(), op.clone()) {
+            let cost = done.cycles() - base;
+            let (Some(lo), Some(hi)) = (
+                window.start().checked_sub(cost),
+                window.end().checked_sub(cost),
+            ) else {
+                continue;
+            };
+            for padded in self.within(lo.min(hi)..=hi) {
+                out.extend(padded.on(this.clone(), op.clone()).into_iter().filter(|s| {
+                    s.cycles() - padded.cycles() == cost && window.contains(&s.cycles())
+                }));
+            }
+        }
+        out
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state holding value 1, loaded with `value`, in `reg`.
+    fn loaded<R: RegFile>(reg: R, value: u8) -> State<u32, R> {
+        State::default()
+            .on(1, Op::Const(value))
+            .into_iter()
+            .find(|s| s.regmap[&1].0 == reg)
+            .unwrap()
+    }
+    /// Whether every way of getting value 1 again has to reload it.
+    fn reloads<R: RegFile>(s: &State<u32, R>) -> bool {
+        let out = s.on(3, Op::Just(1));
+        !out.is_empty()
+            && out
+                .iter()
+                .all(|s| s.insts.iter().any(|i| matches!(i, Inst::StoreArg { .. })))
+    }
+
+    #[test]
+    fn live_value_is_reused() {
+        let s = loaded(Reg::A, 0x15);
+        let out = s.on(3, Op::Just(1));
+        assert_eq!(out.len(), 1);
+        assert_eq!(out.first().unwrap().insts, s.insts);
+    }
+    #[test]
+    fn arith_clobbers_acc() {
+        let s = loaded(Reg::A, 0x15);
+        for d in s.on(2, Op::DecimalAdd(1, Source::Const(1))) {
+            assert_eq!(d.regmap[&2].0, Reg::A);
+            assert!(reloads(&d));
         }
     }
+    #[test]
+    fn decimal_adjust_clobbers_acc() {
+        let mut s = loaded(z80::Reg::A, 0x15);
+        let (r, i) = s.regmap[&1];
+        s.insts.push(Inst::DecimalAdjust);
+        assert!(s.sets_at(i, r));
+        assert!(reloads(&s));
+    }
+    #[test]
+    fn call_clobbers_every_register() {
+        for &r in Reg::ALL {
+            let s = loaded(r, 0x15).call(0x1234);
+            assert!(reloads(&s));
+        }
+    }
+    #[test]
+    fn pads_to_exact_cycles() {
+        let s = loaded(Reg::A, 5);
+        assert_eq!(s.cycles(), 2);
+        let padded = s.pad_to(9);
+        assert!(!padded.is_empty());
+        for p in &padded {
+            assert_eq!(p.cycles(), 9);
+            // LDA #, then two NOPs and a BIT zp in some order
+            assert_eq!(p.insts.len(), 4);
+        }
+        // no pad takes a single cycle, and a block cannot be shortened
+        assert!(s.pad_to(3).is_empty());
+        assert!(s.pad_to(1).is_empty());
+        assert_eq!(s.within(3..=4).first().unwrap().cycles(), 4);
+        assert_eq!(s.within(5..=20).first().unwrap().cycles(), 5);
+        assert!(s.within(0..=1).is_empty());
+        assert!(s.within(3..=3).is_empty());
+    }
+    #[test]
+    fn no_padding_after_branch() {
+        let c = loaded(Reg::X, 5)
+            .on(2, Op::Compare(1, Source::Mem(0x10)))
+            .into_iter()
+            .next()
+            .unwrap();
+        let b = c.branch(&2, Cond::Eq, 1).pop_first().unwrap();
+        let end = b.cycles();
+        assert_eq!(b.pad_to(end).len(), 1);
+        assert!(b.pad_to(end + 2).is_empty());
+        assert!(b.within(end + 1..=end + 9).is_empty());
+        assert_eq!(b.within(end..=end + 9).first().unwrap().cycles(), end);
+    }
+    #[test]
+    fn on_within_pads_ahead() {
+        let s: State<u32> = State::default();
+        let out = s.on_within(1, Op::Const(5), 7..=7);
+        // a load into each register after a BIT zp and NOP
+        assert_eq!(out.len(), 3);
+        for o in &out {
+            assert_eq!(o.cycles(), 7);
+            assert!(matches!(o.insts.last(), Some(Inst::LoadConst { .. })));
+        }
+    }
+    #[test]
+    fn compare_patches_operand() {
+        let x = loaded(Reg::X, 5);
+        let y = x
+            .on(2, Op::Const(7))
+            .into_iter()
+            .find(|s| s.regmap[&2].0 == Reg::Y)
+            .unwrap();
+        let out = y.on(3, Op::Compare(1, Source::Value(2)));
+        // CPX # patched from Y, without going through A
+        assert!(
+            out.iter()
+                .any(|s| s.insts.len() == 4 && !s.flags.as_ref().unwrap().swapped)
+        );
+        let lt: Vec<_> = out.iter().flat_map(|s| s.branch(&3, Cond::Lt, 9)).collect();
+        assert!(!lt.is_empty());
+        assert!(lt.iter().all(|s| !s.flags.as_ref().unwrap().swapped));
+    }
+    #[test]
+    fn branch_needs_live_flags() {
+        let s = loaded(Reg::X, 5);
+        let out = s.on(2, Op::Compare(1, Source::Mem(0x10)));
+        let c = out
+            .iter()
+            .find(|s| {
+                s.insts.last()
+                    == Some(&Inst::Compare {
+                        reg: Reg::X,
+                        with: Operand::Abs(0x10),
+                    })
+            })
+            .unwrap();
+        assert_eq!(c.branch(&2, Cond::Eq, 1).len(), 1);
+        // a different comparison
+        assert!(c.branch(&1, Cond::Eq, 1).is_empty());
+        // flags clobbered by a load
+        let d = c.on(3, Op::Const(1)).into_iter().next().unwrap();
+        assert!(d.branch(&2, Cond::Eq, 1).is_empty());
+    }
 }
@@ -4,11 +4,118 @@ use alloc::{
     collections::{btree_map::BTreeMap, btree_set::BTreeSet},
     vec::{self, Vec},
 };
+use core::{fmt::Debug, ops::RangeInclusive};
 extern crate alloc;
+/// A register file the block search can place values in.
+///
+/// Implementations describe which registers exist and which single
+/// instructions move values between them; the search in [`block::State`]
+/// only ever emits moves and loads the register file allows.
+pub trait RegFile: Copy + Ord + Debug + 'static {
+    /// Every register the search may place a value in.
+    const ALL: &'static [Self];
+    /// The accumulator, which arithmetic results end up in.
+    const ACC: Self;
+    /// Whether a single instruction copies `from` into `to`.
+    fn can_transfer(from: Self, to: Self) -> bool;
+    /// Whether an immediate can be loaded directly into this register.
+    fn can_load(self) -> bool {
+        true
+    }
+    /// Whether this register can be stored to an absolute address, which
+    /// is what patching a later immediate operand requires.
+    fn can_store(self) -> bool {
+        true
+    }
+    /// Registers other than this one that storing it to an absolute
+    /// address overwrites, such as a pointer the store has to go through.
+    fn store_clobbers(self) -> &'static [Self] {
+        &[]
+    }
+    /// Exchange instructions, each given as the register swaps it performs
+    /// at once (e.g. Z80 `EX DE,HL` swaps D with H and E with L).
+    const EXCHANGES: &'static [&'static [(Self, Self)]] = &[];
+    /// Cycle counts of the available padding instructions, none of which
+    /// changes a register (e.g. 6502 `NOP` and `BIT zp`).
+    const PADS: &'static [u32];
+    /// Cycles taken by an instruction.
+    fn cycles(inst: &block::Inst<Self>) -> u32;
+    /// Whether `reg` can be compared against `with` in one instruction.
+    fn can_compare(reg: Self, with: &block::Operand<Self>) -> bool;
+    /// Whether an instruction changes the flags a compare left behind.
+    fn sets_flags(inst: &block::Inst<Self>) -> bool;
+    /// Whether the accumulator can be added to or subtracted from `with`.
+    fn can_arith(with: &block::Operand<Self>) -> bool;
+    /// Whether decimal arithmetic is selected by a flag (6502 `SED`/`CLD`)
+    /// rather than by adjusting each result (Z80 `DAA`).
+    const DECIMAL_FLAG: bool = false;
+    /// Whether add and subtract always take the carry in, so it has to be
+    /// set up before each one.
+    const CARRY_IN: bool = false;
+}
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Reg {
     A,
     X,
     Y,
 }
-pub mod block;
\ No newline at end of file
+impl RegFile for Reg {
+    const ALL: &'static [Self] = &[Reg::A, Reg::X, Reg::Y];
+    const ACC: Self = Reg::A;
+    // NOP, BIT zp
+    const PADS: &'static [u32] = &[2, 3];
+    const DECIMAL_FLAG: bool = true;
+    const CARRY_IN: bool = true;
+    fn cycles(inst: &block::Inst<Self>) -> u32 {
+        match inst {
+            block::Inst::StoreArg { .. } => 4,
+            block::Inst::LoadConst { .. } => 2,
+            block::Inst::Transfer { .. } => 2,
+            block::Inst::Exchange { .. } => 0,
+            block::Inst::Pad { cycles } => *cycles,
+            block::Inst::Compare { with, .. } => match with {
+                block::Operand::Abs(_) => 4,
+                _ => 2,
+            },
+            // not taken; a taken branch adds one cycle, two across a page
+            block::Inst::Branch { .. } => 2,
+            block::Inst::Arith { with, .. } => match with {
+                block::Operand::Abs(_) => 4,
+                _ => 2,
+            },
+            block::Inst::SetCarry(_) | block::Inst::SetDecimal(_) => 2,
+            block::Inst::DecimalAdjust => 0,
+            block::Inst::Call { .. } => 6,
+        }
+    }
+    fn can_arith(with: &block::Operand<Self>) -> bool {
+        // ADC and SBC only take immediate or memory operands
+        !matches!(with, block::Operand::Reg(_))
+    }
+    fn can_compare(_reg: Self, with: &block::Operand<Self>) -> bool {
+        // CMP, CPX and CPY only take immediate or memory operands
+        !matches!(with, block::Operand::Reg(_))
+    }
+    fn sets_flags(inst: &block::Inst<Self>) -> bool {
+        match inst {
+            block::Inst::LoadConst { .. }
+            | block::Inst::Transfer { .. }
+            | block::Inst::Compare { .. }
+            | block::Inst::Arith { .. }
+            | block::Inst::SetCarry(_)
+            | block::Inst::Call { .. } => true,
+            // BIT zp
+            block::Inst::Pad { cycles } => *cycles == 3,
+            _ => false,
+        }
+    }
+    fn can_transfer(from: Self, to: Self) -> bool {
+        // TAX, TAY, TXA and TYA; there is no direct X <-> Y transfer
+        matches!(
+            (from, to),
+            (Reg::A, Reg::X) | (Reg::A, Reg::Y) | (Reg::X, Reg::A) | (Reg::Y, Reg::A)
+        )
+    }
+}
+pub mod block;
+pub mod z80;
@@ -0,0 +1,226 @@
+//! Zilog Z80 register model.
+use super::*;
+
+/// An 8-bit Z80 register.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Reg {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+}
+/// A Z80 register pair.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Pair {
+    BC,
+    DE,
+    HL,
+}
+impl Pair {
+    /// The high and low halves of the pair.
+    pub fn halves(self) -> (Reg, Reg) {
+        match self {
+            Pair::BC => (Reg::B, Reg::C),
+            Pair::DE => (Reg::D, Reg::E),
+            Pair::HL => (Reg::H, Reg::L),
+        }
+    }
+    /// Both halves, high first.
+    pub const fn regs(self) -> &'static [Reg] {
+        match self {
+            Pair::BC => &[Reg::B, Reg::C],
+            Pair::DE => &[Reg::D, Reg::E],
+            Pair::HL => &[Reg::H, Reg::L],
+        }
+    }
+}
+/// `EX DE,HL`.
+pub const EX_DE_HL: &[(Reg, Reg)] = &[(Reg::D, Reg::H), (Reg::E, Reg::L)];
+impl RegFile for Reg {
+    const ALL: &'static [Self] = &[Reg::A, Reg::B, Reg::C, Reg::D, Reg::E, Reg::H, Reg::L];
+    const ACC: Self = Reg::A;
+    const EXCHANGES: &'static [&'static [(Self, Self)]] = &[EX_DE_HL];
+    // NOP, CP (HL), JR $+2
+    const PADS: &'static [u32] = &[4, 7, 12];
+    fn cycles(inst: &block::Inst<Self>) -> u32 {
+        match inst {
+            block::Inst::StoreArg { reg, .. } => match reg {
+                Reg::A => 13,
+                // LD A,r then LD (nn),A, or LD HL,nn then LD (HL),r
+                _ => 17,
+            },
+            block::Inst::LoadConst { .. } => 7,
+            block::Inst::Transfer { .. } => 4,
+            block::Inst::Exchange { .. } => 4,
+            block::Inst::Pad { cycles } => *cycles,
+            block::Inst::Compare { with, .. } => match with {
+                block::Operand::Reg(_) => 4,
+                _ => 7,
+            },
+            // JP cc,nn takes the same time whether or not it is taken
+            block::Inst::Branch { .. } => 10,
+            block::Inst::Arith { with, .. } => match with {
+                block::Operand::Reg(_) => 4,
+                _ => 7,
+            },
+            block::Inst::SetCarry(_) | block::Inst::DecimalAdjust => 4,
+            block::Inst::SetDecimal(_) => 0,
+            block::Inst::Call { .. } => 17,
+        }
+    }
+    fn can_arith(with: &block::Operand<Self>) -> bool {
+        // ADD A,r / ADD A,n and SUB r / SUB n
+        !matches!(with, block::Operand::Abs(_))
+    }
+    fn can_compare(reg: Self, with: &block::Operand<Self>) -> bool {
+        // CP r and CP n; there is no CP (nn)
+        reg == Reg::A && !matches!(with, block::Operand::Abs(_))
+    }
+    fn sets_flags(inst: &block::Inst<Self>) -> bool {
+        match inst {
+            block::Inst::Compare { .. }
+            | block::Inst::Arith { .. }
+            | block::Inst::DecimalAdjust
+            | block::Inst::SetCarry(_)
+            | block::Inst::Call { .. } => true,
+            // CP (HL)
+            block::Inst::Pad { cycles } => *cycles == 7,
+            _ => false,
+        }
+    }
+    fn can_transfer(from: Self, to: Self) -> bool {
+        // LD r,r' works between any two 8-bit registers
+        from != to
+    }
+    fn store_clobbers(self) -> &'static [Self] {
+        match self {
+            // LD (nn),A
+            Reg::A => &[],
+            // through A, as LD (nn),HL would also write the byte after
+            Reg::H | Reg::L => &[Reg::A],
+            // through HL, with LD (HL),r
+            _ => Pair::HL.regs(),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use block::{Inst, Op, Source, State};
+
+    /// `s` with value `v` loaded into `reg`.
+    fn load(s: &State<u32, Reg>, v: u32, reg: Reg) -> State<u32, Reg> {
+        s.on(v, Op::Const(v as u8))
+            .into_iter()
+            .find(|s| s.regmap[&v].0 == reg)
+            .unwrap()
+    }
+
+    #[test]
+    fn spills_through_hl() {
+        // 2 in H, 1 in B, then B reloaded with 3
+        let s = load(&State::default(), 2, Reg::H);
+        let s = load(&load(&s, 1, Reg::B), 3, Reg::B);
+        let out = s.on(4, Op::Just(1));
+        assert!(!out.is_empty());
+        for o in &out {
+            assert_eq!(
+                o.insts[2],
+                Inst::StoreArg {
+                    reg: Reg::B,
+                    fwd: 2
+                }
+            );
+            // LD H,n; LD B,n; LD HL,nn; LD (HL),B; LD B,n; LD r,n
+            assert_eq!(o.cycles(), 7 + 7 + 17 + 7 + 7);
+            // the store went through HL, so 2 has to be patched in too
+            assert!(o.sets_at(o.regmap[&2].1, Reg::H));
+        }
+        let again = out.first().unwrap().on(5, Op::Just(2));
+        assert!(!again.is_empty());
+        for o in &again {
+            // LD A,H; LD (nn),A just after LD H,n
+            assert_eq!(
+                o.insts[1],
+                Inst::StoreArg {
+                    reg: Reg::H,
+                    fwd: 5
+                }
+            );
+        }
+    }
+    #[test]
+    fn spill_keeps_hl_that_is_read_later() {
+        // 1 in B, 2 in H read by LD A,H, then B reloaded
+        let s = load(&load(&State::default(), 1, Reg::B), 2, Reg::H);
+        let s = s.into_reg(&2, Reg::A).into_iter().next().unwrap();
+        assert_eq!(
+            s.insts.last(),
+            Some(&Inst::Transfer {
+                from: Reg::H,
+                to: Reg::A
+            })
+        );
+        let s = load(&s, 3, Reg::B);
+        assert!(s.on(4, Op::Just(1)).is_empty());
+    }
+    #[test]
+    fn spill_keeps_the_compared_register() {
+        // 1 in A, 2 in H, then H reloaded: CP n can't be patched from H
+        // through A, which holds the other side of the compare
+        let s = load(&load(&State::default(), 1, Reg::A), 2, Reg::H);
+        let s = load(&s, 3, Reg::H);
+        let out = s.on(4, Op::Compare(1, Source::Value(2)));
+        assert!(!out.is_empty());
+        for o in &out {
+            let lhs = if o.flags.as_ref().unwrap().swapped {
+                2
+            } else {
+                1
+            };
+            let (r, i) = o.regmap[&lhs];
+            assert!(matches!(o.insts.last(), Some(Inst::Compare { reg, .. }) if *reg == r));
+            assert!(!o.sets_at(i, r));
+        }
+    }
+    #[test]
+    fn exchange_moves_value() {
+        let s: State<u32, Reg> = State::default();
+        let loads = s.on(1, Op::Const(5));
+        assert_eq!(loads.len(), Reg::ALL.len());
+        let d = loads
+            .into_iter()
+            .find(|s| s.regmap[&1].0 == Reg::D)
+            .unwrap();
+        let h = d.into_reg(&1, Reg::H);
+        // LD H,D or EX DE,HL
+        assert_eq!(h.len(), 2);
+        assert!(
+            h.iter()
+                .any(|s| s.insts.last() == Some(&Inst::Exchange { swaps: EX_DE_HL }))
+        );
+        for s in &h {
+            assert_eq!(s.regmap[&1].0, Reg::H);
+        }
+    }
+    #[test]
+    fn exchange_clobbers_swapped_value() {
+        let s: State<u32, Reg> = State::default();
+        let d = s
+            .on(1, Op::Const(5))
+            .into_iter()
+            .find(|s| s.regmap[&1].0 == Reg::D)
+            .unwrap();
+        let h = d
+            .on(2, Op::Const(7))
+            .into_iter()
+            .find(|s| s.regmap[&2].0 == Reg::H);
+        let x = h.unwrap().exchange(EX_DE_HL);
+        assert_eq!(x.regmap[&1].0, Reg::H);
+        assert_eq!(x.regmap[&2].0, Reg::D);
+        assert!(!x.sets_at(x.regmap[&1].1, Reg::H));
+    }
+}
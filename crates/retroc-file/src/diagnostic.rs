@@ -0,0 +1,252 @@
+//! Parse errors that remember where they happened.
+use crate::{ErrorKind, Locate};
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Display, Write};
+use nom::error::{ContextError, FromExternalError, ParseError};
+
+/// A position in the metadata text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct Location {
+    /// Byte offset from the start of the text.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, in characters.
+    pub column: usize,
+}
+
+impl Location {
+    /// The location of byte `offset` in `text`.
+    pub fn of(text: &str, offset: usize) -> Self {
+        let before = &text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// A parse error with its location in the metadata text and binary stream,
+/// the section it happened in and the contexts it was raised through.
+///
+/// Parsers only see the input left at the point of failure, so positions
+/// are recorded as they are raised and resolved into a [`Location`] and
+/// byte offset by [`Locate::locate`], which `FileRegistry::parse_file`
+/// calls with the full streams.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Diagnostic {
+    pub kind: ErrorKind,
+    /// Where in the metadata text the error happened.
+    pub location: Option<Location>,
+    /// Offset in the binary stream the error happened at.
+    pub byte_offset: Option<usize>,
+    /// The `arch` name of the section being parsed.
+    pub arch: Option<String>,
+    /// Contexts the error was raised through, innermost first.
+    pub context: Vec<&'static str>,
+    meta_at: Option<usize>,
+    bytes_at: Option<usize>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            location: None,
+            byte_offset: None,
+            arch: None,
+            context: Vec::new(),
+            meta_at: None,
+            bytes_at: None,
+        }
+    }
+
+    /// Render the error with the offending line of `meta` and a caret
+    /// under the column it happened at.
+    pub fn render(&self, meta: &str) -> String {
+        let mut s = String::new();
+        self.write_rendered(&mut s, meta).unwrap();
+        s
+    }
+
+    /// Render the error into a writer, see [`Diagnostic::render`].
+    pub fn write_rendered<W: Write>(&self, w: &mut W, meta: &str) -> fmt::Result {
+        writeln!(w, "error: {}", self.kind)?;
+        let mut at = String::new();
+        if let Some(arch) = &self.arch {
+            write!(at, "arch {}", arch)?;
+        }
+        if let Some(loc) = self.location {
+            if !at.is_empty() {
+                at.push_str(", ");
+            }
+            write!(at, "line {}, column {}", loc.line, loc.column)?;
+        }
+        if let Some(off) = self.byte_offset {
+            if !at.is_empty() {
+                at.push_str(", ");
+            }
+            write!(at, "byte {:#x}", off)?;
+        }
+        if !at.is_empty() {
+            writeln!(w, "  --> {}", at)?;
+        }
+        if let Some(loc) = self.location {
+            let start = meta[..loc.offset].rfind('\n').map_or(0, |i| i + 1);
+            let line = meta[start..].lines().next().unwrap_or("");
+            let num = alloc::format!("{}", loc.line);
+            writeln!(w, "{:width$} |", "", width = num.len())?;
+            writeln!(w, "{} | {}", num, line)?;
+            writeln!(
+                w,
+                "{:width$} | {:col$}^",
+                "",
+                "",
+                width = num.len(),
+                col = loc.column - 1
+            )?;
+        }
+        for ctx in &self.context {
+            writeln!(w, "  = while parsing {}", ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(arch) = &self.arch {
+            write!(f, "in arch {}: ", arch)?;
+        }
+        if let Some(loc) = self.location {
+            write!(f, "line {}, column {}: ", loc.line, loc.column)?;
+        }
+        if let Some(off) = self.byte_offset {
+            write!(f, "byte {:#x}: ", off)?;
+        }
+        write!(f, "{}", self.kind)?;
+        for ctx in &self.context {
+            write!(f, " (while parsing {})", ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ErrorKind> for Diagnostic {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl Locate for Diagnostic {
+    fn at_meta(mut self, rest: &str) -> Self {
+        self.meta_at.get_or_insert(rest.as_ptr() as usize);
+        self
+    }
+    fn locate(mut self, meta: &str, bytes: &[u8], arch: &str) -> Self {
+        // pointers into the streams are only meaningful if they fall inside them
+        if self.location.is_none()
+            && let Some(off) = self
+                .meta_at
+                .and_then(|p| p.checked_sub(meta.as_ptr() as usize))
+            && off <= meta.len()
+        {
+            self.location = Some(Location::of(meta, off));
+        }
+        if self.byte_offset.is_none()
+            && let Some(off) = self
+                .bytes_at
+                .and_then(|p| p.checked_sub(bytes.as_ptr() as usize))
+            && off <= bytes.len()
+        {
+            self.byte_offset = Some(off);
+        }
+        self.arch.get_or_insert_with(|| String::from(arch));
+        self
+    }
+}
+
+impl<'a> ParseError<&'a str> for Diagnostic {
+    fn from_error_kind(input: &'a str, kind: nom::error::ErrorKind) -> Self {
+        Self::new(ErrorKind::Nom(kind)).at_meta(input)
+    }
+    fn append(_input: &'a str, _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a> ParseError<&'a [u8]> for Diagnostic {
+    fn from_error_kind(input: &'a [u8], kind: nom::error::ErrorKind) -> Self {
+        let mut d = Self::new(ErrorKind::Nom(kind));
+        d.bytes_at = Some(input.as_ptr() as usize);
+        d
+    }
+    fn append(_input: &'a [u8], _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a> FromExternalError<&'a str, ErrorKind> for Diagnostic {
+    fn from_external_error(input: &'a str, _kind: nom::error::ErrorKind, e: ErrorKind) -> Self {
+        Self::new(e).at_meta(input)
+    }
+}
+
+impl<I> ContextError<I> for Diagnostic {
+    fn add_context(_input: I, ctx: &'static str, mut other: Self) -> Self {
+        other.context.push(ctx);
+        other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileParser, FileRegistry, section::Section};
+    use nom::Parser;
+
+    /// A length byte followed by that many bytes.
+    struct Counted;
+    impl FileParser<Vec<u8>, Diagnostic> for Counted {
+        fn from_bytes_and_meta<'a, 'b>(
+            &self,
+            bytes: &'a [u8],
+            meta: &'b str,
+            _section: &Section,
+            _registry: &FileRegistry<'_, Vec<u8>, Diagnostic>,
+        ) -> Result<(&'a [u8], &'b str, Vec<u8>), nom::Err<Diagnostic>> {
+            let (bytes, data) =
+                nom::multi::length_data(nom::number::complete::u8).parse_complete(bytes)?;
+            Ok((bytes, meta, data.to_vec()))
+        }
+    }
+
+    #[test]
+    fn located_in_full_streams() {
+        let mut registry = FileRegistry::default();
+        registry.register_parser("counted".into(), &Counted);
+        let bytes = [2, 0xaa, 0xbb, 5, 0xcc];
+        let nom::Err::Error(e) = registry
+            .parse_file(&bytes, "arch counted\narch counted\n")
+            .unwrap_err()
+        else {
+            panic!()
+        };
+        assert_eq!(e.kind, ErrorKind::Nom(nom::error::ErrorKind::Eof));
+        // the second section's payload, counted from the start of the file
+        assert_eq!(e.byte_offset, Some(4));
+        assert_eq!(e.arch.as_deref(), Some("counted"));
+
+        let nom::Err::Error(e) = registry
+            .parse_file(&bytes, "arch counted\narch none\n")
+            .unwrap_err()
+        else {
+            panic!()
+        };
+        assert_eq!(e.kind, ErrorKind::NoParser);
+        assert_eq!(e.location.map(|l| (l.line, l.column)), Some((2, 1)));
+        assert_eq!(e.byte_offset, None);
+    }
+}
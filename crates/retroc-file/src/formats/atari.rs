@@ -0,0 +1,479 @@
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, TryAsRef};
+use alloc::{string::String, vec::Vec};
+
+/// One load segment of an Atari 8-bit executable.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct XexSegment {
+    /// Whether the segment is preceded by a `$FFFF` marker. The first
+    /// segment always is.
+    pub header: bool,
+    pub start: u16,
+    pub data: Vec<u8>,
+}
+
+impl XexSegment {
+    fn word_at(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(self.start)? as usize;
+        let bytes = self.data.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+/// An Atari 8-bit DOS executable (`.xex`): a sequence of segments, each a
+/// little-endian start and end address followed by the bytes between them.
+///
+/// The run and init addresses are ordinary segments loading into
+/// [`Xex::RUNAD`] and [`Xex::INITAD`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Xex {
+    pub segments: Vec<XexSegment>,
+}
+
+impl Xex {
+    /// Vector jumped through once the whole file has loaded.
+    pub const RUNAD: u16 = 0x02e0;
+    /// Vector jumped through after each segment that writes it.
+    pub const INITAD: u16 = 0x02e2;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        if !bytes.starts_with(&[0xff, 0xff]) {
+            return Err(ErrorKind::BadMagic);
+        }
+        let mut segments = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let header = rest.starts_with(&[0xff, 0xff]);
+            if header {
+                rest = &rest[2..];
+            }
+            let [s0, s1, e0, e1, tail @ ..] = rest else {
+                return Err(ErrorKind::Truncated);
+            };
+            let start = u16::from_le_bytes([*s0, *s1]);
+            let end = u16::from_le_bytes([*e0, *e1]);
+            let len = (end.checked_sub(start).ok_or(ErrorKind::Corrupt)? as usize) + 1;
+            let (data, tail) = tail.split_at_checked(len).ok_or(ErrorKind::Truncated)?;
+            segments.push(XexSegment {
+                header,
+                start,
+                data: data.to_vec(),
+            });
+            rest = tail;
+        }
+        if segments.is_empty() {
+            return Err(ErrorKind::Truncated);
+        }
+        Ok(Self { segments })
+    }
+
+    /// Write the whole file. Empty segments and segments running past
+    /// $FFFF are [`ErrorKind::Format`] errors.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorKind> {
+        let mut out = Vec::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            let end = (segment.data.len() as u32)
+                .checked_sub(1)
+                .map(|len| segment.start as u32 + len)
+                .filter(|&end| end <= 0xffff)
+                .ok_or(ErrorKind::Format)?;
+            if i == 0 || segment.header {
+                out.extend([0xff, 0xff]);
+            }
+            out.extend(segment.start.to_le_bytes());
+            out.extend((end as u16).to_le_bytes());
+            out.extend_from_slice(&segment.data);
+        }
+        Ok(out)
+    }
+
+    /// The run address, from the last segment that writes all of RUNAD.
+    pub fn run_address(&self) -> Option<u16> {
+        self.segments
+            .iter()
+            .rev()
+            .find_map(|s| s.word_at(Self::RUNAD))
+    }
+
+    /// The init addresses, each with the index of the segment after which
+    /// the loader calls it.
+    pub fn init_addresses(&self) -> Vec<(usize, u16)> {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((i, s.word_at(Self::INITAD)?)))
+            .collect()
+    }
+
+    /// Everything the file loads, as one image. Files that write the same
+    /// address twice with different values, such as several INITAD
+    /// segments, give [`ErrorKind::Overlap`]; use [`Xex::sections`] for
+    /// those.
+    pub fn to_image(&self) -> Result<MemoryImage, ErrorKind> {
+        let mut image = MemoryImage::new();
+        for segment in &self.segments {
+            image.insert(segment.start as u64, &segment.data)?;
+        }
+        Ok(image)
+    }
+
+    /// A file loading each run of `image`, followed by a RUNAD segment if
+    /// `run` is given. The image must fit below $10000.
+    pub fn from_image(image: &MemoryImage, run: Option<u16>) -> Result<Self, ErrorKind> {
+        if image.end().is_some_and(|end| end > 0x10000) {
+            return Err(ErrorKind::Format);
+        }
+        let mut segments: Vec<_> = image
+            .segments()
+            .map(|(start, data)| XexSegment {
+                header: false,
+                start: start as u16,
+                data: data.to_vec(),
+            })
+            .collect();
+        if let Some(run) = run {
+            segments.push(XexSegment {
+                header: false,
+                start: Self::RUNAD,
+                data: run.to_le_bytes().to_vec(),
+            });
+        }
+        if segments.is_empty() {
+            return Err(ErrorKind::Truncated);
+        }
+        segments[0].header = true;
+        Ok(Self { segments })
+    }
+
+    /// Each segment as a section originating at its start address.
+    pub fn sections(&self, arch: &str) -> Vec<(Section, MemoryImage)> {
+        self.segments
+            .iter()
+            .map(|segment| {
+                let mut section = Section::new(arch);
+                section.org = Some(segment.start as u64);
+                let image = MemoryImage::from_bytes(segment.start as u64, &segment.data);
+                (section, image)
+            })
+            .collect()
+    }
+}
+
+/// The Atari DOS executable format, taking the whole remaining byte stream
+/// as one `.xex` file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XexParser;
+
+impl<T: From<Xex>, Err: From<ErrorKind>> FileParser<T, Err> for XexParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let xex = Xex::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(xex)))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        // the marker is short, so only trust it if the segments line up
+        Xex::from_bytes(bytes).ok()?;
+        Some(if extension == Some("xex") { 90 } else { 60 })
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("Atari 8-bit executable"))
+    }
+}
+
+impl<T: TryAsRef<Xex>, Err: From<ErrorKind>> FileWriter<T, Err> for XexParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let xex = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend(xex.to_bytes()?);
+        Ok(())
+    }
+}
+
+/// Atari 2600 cartridge bank-switching schemes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum BankScheme {
+    /// 2 KiB or 4 KiB, no switching.
+    Plain,
+    /// Two 4 KiB banks, switched by accessing $1FF8–$1FF9.
+    F8,
+    /// Four 4 KiB banks, switched by accessing $1FF6–$1FF9.
+    F6,
+    /// Eight 4 KiB banks, switched by accessing $1FF4–$1FFB.
+    F4,
+    /// Parker Bros.: eight 1 KiB slices, the last fixed at $1C00.
+    E0,
+    /// Tigervision `3F`: 2 KiB banks written to $003F, the last fixed at
+    /// $1800.
+    Tigervision,
+    /// Activision `FE`: two 4 KiB banks selected by the stack at $01FE.
+    Activision,
+}
+
+// Instruction sequences that only turn up in carts of one scheme, as used by
+// the common emulators' detection.
+const E0_SIGNATURES: [[u8; 3]; 8] = [
+    [0x8d, 0xe0, 0x1f], // STA $1FE0
+    [0x8d, 0xe0, 0x5f], // STA $5FE0
+    [0x8d, 0xe9, 0xff], // STA $FFE9
+    [0x0c, 0xe0, 0x1f], // NOP $1FE0
+    [0xad, 0xe0, 0x1f], // LDA $1FE0
+    [0xad, 0xe9, 0xff], // LDA $FFE9
+    [0xad, 0xed, 0xff], // LDA $FFED
+    [0xad, 0xf3, 0xbf], // LDA $BFF3
+];
+const TIGERVISION_SIGNATURE: [u8; 2] = [0x85, 0x3f]; // STA $3F
+const ACTIVISION_SIGNATURES: [[u8; 5]; 4] = [
+    [0x20, 0x00, 0xd0, 0xc6, 0xc5], // JSR $D000; DEC $C5
+    [0x20, 0xc3, 0xf8, 0xa5, 0x82], // JSR $F8C3; LDA $82
+    [0xd0, 0xfb, 0x20, 0x73, 0xfe], // BNE *-3; JSR $FE73
+    [0x20, 0x00, 0xf0, 0x84, 0xd6], // JSR $F000; STY $D6
+];
+
+fn count(haystack: &[u8], needle: &[u8]) -> usize {
+    haystack
+        .windows(needle.len())
+        .filter(|w| *w == needle)
+        .count()
+}
+
+impl BankScheme {
+    /// Guess the scheme of a ROM image from its size and contents, along
+    /// with whether it carries a Superchip (128 bytes of RAM at the start of
+    /// each bank, read back as a copy of its first 128 bytes).
+    ///
+    /// Sizes no scheme here covers are [`ErrorKind::Unsupported`].
+    pub fn detect(rom: &[u8]) -> Result<(Self, bool), ErrorKind> {
+        let superchip = || {
+            rom.chunks(0x1000)
+                .all(|bank| bank.len() >= 0x100 && bank[..0x80] == bank[0x80..0x100])
+        };
+        let tigervision = || count(rom, &TIGERVISION_SIGNATURE) >= 2;
+        let scheme = match rom.len() {
+            0x800 | 0x1000 => return Ok((Self::Plain, false)),
+            0x2000 if E0_SIGNATURES.iter().any(|s| count(rom, s) > 0) => Self::E0,
+            0x2000 if tigervision() => Self::Tigervision,
+            0x2000 if ACTIVISION_SIGNATURES.iter().any(|s| count(rom, s) > 0) => Self::Activision,
+            0x2000 => Self::F8,
+            0x4000 if tigervision() => Self::Tigervision,
+            0x4000 => Self::F6,
+            0x8000 if tigervision() => Self::Tigervision,
+            0x8000 => Self::F4,
+            len if len > 0 && len.is_multiple_of(0x800) && tigervision() => Self::Tigervision,
+            _ => return Err(ErrorKind::Unsupported),
+        };
+        let banked = matches!(scheme, Self::F8 | Self::F6 | Self::F4);
+        Ok((scheme, banked && superchip()))
+    }
+
+    /// Size of each bank, or of the whole ROM when there is no switching.
+    pub fn bank_len(self, rom_len: usize) -> usize {
+        match self {
+            Self::Plain => rom_len,
+            Self::F8 | Self::F6 | Self::F4 | Self::Activision => 0x1000,
+            Self::E0 => 0x400,
+            Self::Tigervision => 0x800,
+        }
+    }
+
+    /// Where bank `bank` of `banks` appears in the 13-bit cartridge address
+    /// space, if it has a fixed place.
+    pub fn origin(self, bank: usize, banks: usize) -> Option<u64> {
+        match self {
+            Self::Plain | Self::F8 | Self::F6 | Self::F4 | Self::Activision => Some(0x1000),
+            Self::E0 if bank == 7 => Some(0x1c00),
+            Self::E0 => None,
+            Self::Tigervision if bank + 1 == banks => Some(0x1800),
+            Self::Tigervision => Some(0x1000),
+        }
+    }
+}
+
+/// An Atari 2600 cartridge image (`.a26` / `.bin`).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Atari2600Rom {
+    pub scheme: BankScheme,
+    pub superchip: bool,
+    pub data: Vec<u8>,
+}
+
+impl Atari2600Rom {
+    /// Take a raw ROM image, detecting its bank-switching scheme.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        let (scheme, superchip) = BankScheme::detect(bytes)?;
+        Ok(Self {
+            scheme,
+            superchip,
+            data: bytes.to_vec(),
+        })
+    }
+
+    pub fn banks(&self) -> impl Iterator<Item = &[u8]> {
+        self.data
+            .chunks(self.scheme.bank_len(self.data.len()).max(1))
+    }
+
+    /// The reset vector at the end of the last bank.
+    pub fn reset_vector(&self) -> Option<u16> {
+        let [lo, hi, _, _] = *self.data.last_chunk::<4>()?;
+        Some(u16::from_le_bytes([lo, hi]))
+    }
+
+    /// Each bank as a section. Banked schemes number their sections, and
+    /// banks with a fixed place in the cartridge space originate there; a
+    /// 2 KiB ROM sits in the upper half, where it is mirrored.
+    pub fn sections(&self, arch: &str) -> Vec<(Section, MemoryImage)> {
+        let banks = self.banks().count();
+        self.banks()
+            .enumerate()
+            .map(|(i, bank)| {
+                let mut section = Section::new(arch);
+                section.org = self.scheme.origin(i, banks);
+                if self.scheme == BankScheme::Plain {
+                    section.org = section.org.map(|org| org + 0x1000 - bank.len() as u64);
+                } else {
+                    section.bank = Some(i as u64);
+                }
+                let image = MemoryImage::from_bytes(section.org.unwrap_or(0), bank);
+                (section, image)
+            })
+            .collect()
+    }
+}
+
+/// The Atari 2600 cartridge format, taking the whole remaining byte stream
+/// as one ROM image.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Atari2600Parser;
+
+impl<T: From<Atari2600Rom>, Err: From<ErrorKind>> FileParser<T, Err> for Atari2600Parser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let rom = Atari2600Rom::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(rom)))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        // no signature; a size that fits a scheme and a reset vector into
+        // cartridge space are all there is to go on
+        let rom = Atari2600Rom::from_bytes(bytes).ok()?;
+        let mut confidence = 10;
+        if rom.reset_vector().is_some_and(|v| v & 0x1000 != 0) {
+            confidence += 20;
+        }
+        if extension == Some("a26") {
+            confidence += 50;
+        }
+        Some(confidence)
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("Atari 2600 cartridge"))
+    }
+}
+
+impl<T: TryAsRef<Atari2600Rom>, Err: From<ErrorKind>> FileWriter<T, Err> for Atari2600Parser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let rom = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend_from_slice(&rom.data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const XEX: [u8; 23] = [
+        0xff, 0xff, 0x00, 0x20, 0x02, 0x20, 1, 2, 3, // $2000-$2002
+        0xe2, 0x02, 0xe3, 0x02, 0x00, 0x20, // INITAD, no $FFFF marker
+        0xff, 0xff, 0xe0, 0x02, 0xe1, 0x02, 0x01, 0x20, // RUNAD
+    ];
+
+    #[test]
+    fn xex_round_trip() {
+        let xex = Xex::from_bytes(&XEX).unwrap();
+        assert_eq!(xex.segments.len(), 3);
+        assert!(!xex.segments[1].header && xex.segments[2].header);
+        assert_eq!(xex.run_address(), Some(0x2001));
+        assert_eq!(xex.init_addresses(), [(1, 0x2000)]);
+        assert_eq!(xex.to_bytes().unwrap(), XEX);
+        assert_eq!(xex.sections("6502")[2].0.org, Some(0x2e0));
+        let image = MemoryImage::from_bytes(0x2000, &[1, 2, 3]);
+        let built = Xex::from_image(&image, Some(0x2000)).unwrap();
+        assert_eq!(built.run_address(), Some(0x2000));
+        let back = Xex::from_bytes(&built.to_bytes().unwrap()).unwrap();
+        assert_eq!(back, built);
+    }
+    #[test]
+    fn malformed_xex() {
+        assert_eq!(Xex::from_bytes(&[0, 1]).unwrap_err(), ErrorKind::BadMagic);
+        assert_eq!(
+            Xex::from_bytes(&XEX[..8]).unwrap_err(),
+            ErrorKind::Truncated
+        );
+        assert_eq!(
+            Xex::from_bytes(&XEX[..2]).unwrap_err(),
+            ErrorKind::Truncated
+        );
+    }
+    #[test]
+    fn a2600_banks() {
+        let mut rom = vec![0u8; 0x2000];
+        rom[0x1ffc..0x1ffe].copy_from_slice(&[0x00, 0xf0]);
+        rom[5] = 1;
+        let cart = Atari2600Rom::from_bytes(&rom).unwrap();
+        assert_eq!((cart.scheme, cart.superchip), (BankScheme::F8, false));
+        assert_eq!(cart.reset_vector(), Some(0xf000));
+        let sections = cart.sections("6502");
+        assert_eq!(sections.len(), 2);
+        assert_eq!(
+            (sections[1].0.bank, sections[1].0.org),
+            (Some(1), Some(0x1000))
+        );
+        let plain = Atari2600Rom::from_bytes(&[0xea; 0x800]).unwrap();
+        assert_eq!(plain.sections("6502")[0].0.org, Some(0x1800));
+    }
+    #[test]
+    fn a2600_registry_round_trip() {
+        let mut registry: FileRegistry<Atari2600Rom, ErrorKind> = FileRegistry::default();
+        registry.register_format("a26".into(), &Atari2600Parser);
+        let rom = vec![0xea; 0x1000];
+        let (rest, section, cart) = registry.parse_detected(&rom, Some("a26")).unwrap();
+        assert!(rest.is_empty());
+        let (mut bytes, mut meta) = (Vec::new(), String::new());
+        registry
+            .write_file(&[(section, cart)], &mut bytes, &mut meta)
+            .unwrap();
+        assert_eq!(bytes, rom);
+    }
+    #[test]
+    fn a2600_unsupported_size() {
+        assert_eq!(
+            Atari2600Rom::from_bytes(&[0; 100]).unwrap_err(),
+            ErrorKind::Unsupported
+        );
+    }
+}
@@ -0,0 +1,606 @@
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, TryAsRef};
+use alloc::{string::String, vec::Vec};
+
+/// A Commodore program file: a little-endian load address followed by the
+/// bytes to load there.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Prg {
+    pub load_address: u16,
+    pub data: Vec<u8>,
+}
+
+impl Prg {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        let [lo, hi, data @ ..] = bytes else {
+            return Err(ErrorKind::Truncated);
+        };
+        Ok(Self {
+            load_address: u16::from_le_bytes([*lo, *hi]),
+            data: data.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(2 + self.data.len());
+        out.extend(self.load_address.to_le_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    pub fn to_image(&self) -> MemoryImage {
+        MemoryImage::from_bytes(self.load_address as u64, &self.data)
+    }
+
+    /// A program loading the whole of `image`, with gaps filled by its fill
+    /// byte. The image must be non-empty and fit below $10000.
+    pub fn from_image(image: &MemoryImage) -> Result<Self, ErrorKind> {
+        let (Some(start), Some(end)) = (image.start(), image.end()) else {
+            return Err(ErrorKind::Truncated);
+        };
+        if end > 0x10000 {
+            return Err(ErrorKind::Format);
+        }
+        Ok(Self {
+            load_address: start as u16,
+            data: image.to_bytes(start),
+        })
+    }
+}
+
+/// The `.prg` format, taking the whole remaining byte stream as one program
+/// and producing its memory image.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrgParser;
+
+impl<T: From<MemoryImage>, Err: From<ErrorKind>> FileParser<T, Err> for PrgParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let prg = Prg::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(prg.to_image())))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        // no signature; the usual BASIC start address is a weak hint
+        match (extension, bytes) {
+            (_, [] | [_]) => None,
+            (Some("prg"), _) => Some(50),
+            (_, [0x01, 0x08, ..]) => Some(10),
+            _ => None,
+        }
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("Commodore program file"))
+    }
+}
+
+impl<T: TryAsRef<MemoryImage>, Err: From<ErrorKind>> FileWriter<T, Err> for PrgParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let image = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend(Prg::from_image(image)?.to_bytes());
+        Ok(())
+    }
+}
+
+const SECTOR_LEN: usize = 256;
+const DIR_TRACK: u8 = 18;
+/// Bytes of file data in each sector after the track/sector link.
+const SECTOR_DATA: usize = SECTOR_LEN - 2;
+/// Sectors skipped between consecutive sectors of a file, as the 1541 does.
+const INTERLEAVE: u8 = 10;
+const PAD: u8 = 0xa0;
+
+/// CBM DOS file types (low bits of a directory entry's type byte).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum FileType {
+    Del,
+    Seq,
+    Prg,
+    Usr,
+    Rel,
+    Other(u8),
+}
+
+impl FileType {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x07 {
+            0 => FileType::Del,
+            1 => FileType::Seq,
+            2 => FileType::Prg,
+            3 => FileType::Usr,
+            4 => FileType::Rel,
+            other => FileType::Other(other),
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            FileType::Del => 0,
+            FileType::Seq => 1,
+            FileType::Prg => 2,
+            FileType::Usr => 3,
+            FileType::Rel => 4,
+            FileType::Other(bits) => bits & 0x07,
+        }
+    }
+}
+
+/// One file in a D64 directory.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DirEntry {
+    /// PETSCII name with its $A0 padding removed.
+    pub name: Vec<u8>,
+    pub file_type: FileType,
+    pub closed: bool,
+    pub locked: bool,
+    /// First track and sector of the file's chain.
+    pub start: (u8, u8),
+    /// Size in sectors, as recorded in the directory.
+    pub blocks: u16,
+}
+
+/// A 1541 disk image of 35 or 40 tracks, optionally with error bytes.
+///
+/// The BAM is kept for tracks 1–35 only, so files are only ever allocated
+/// there.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct D64 {
+    data: Vec<u8>,
+    tracks: u8,
+}
+
+impl D64 {
+    /// A freshly formatted 35-track disk.
+    pub fn new(name: &[u8], id: [u8; 2]) -> Self {
+        let mut disk = Self {
+            data: alloc::vec![0; sector_count(35) * SECTOR_LEN],
+            tracks: 35,
+        };
+        let bam = disk.offset(DIR_TRACK, 0).unwrap();
+        let b = &mut disk.data[bam..bam + SECTOR_LEN];
+        b[..4].copy_from_slice(&[DIR_TRACK, 1, b'A', 0]);
+        for t in 1..=35u8 {
+            let n = sectors_per_track(t);
+            let bits = (1u32 << n) - 1;
+            let at = 4 * t as usize;
+            b[at] = n;
+            b[at + 1..at + 4].copy_from_slice(&bits.to_le_bytes()[..3]);
+        }
+        b[0x90..0xab].fill(PAD);
+        write_padded(&mut b[0x90..0xa0], name);
+        b[0xa2..0xa4].copy_from_slice(&id);
+        b[0xa5..0xa7].copy_from_slice(b"2A");
+        let dir = disk.offset(DIR_TRACK, 1).unwrap();
+        disk.data[dir + 1] = 0xff;
+        disk.allocate_sector(DIR_TRACK, 0);
+        disk.allocate_sector(DIR_TRACK, 1);
+        disk
+    }
+
+    /// An image as read from a `.d64` file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        let tracks = [35u8, 40]
+            .into_iter()
+            .find(|&t| {
+                let n = sector_count(t);
+                bytes.len() == n * SECTOR_LEN || bytes.len() == n * (SECTOR_LEN + 1)
+            })
+            .ok_or(ErrorKind::Format)?;
+        Ok(Self {
+            data: bytes.to_vec(),
+            tracks,
+        })
+    }
+
+    /// The image as a `.d64` file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn tracks(&self) -> u8 {
+        self.tracks
+    }
+
+    fn offset(&self, track: u8, sector: u8) -> Result<usize, ErrorKind> {
+        if track == 0 || track > self.tracks || sector >= sectors_per_track(track) {
+            return Err(ErrorKind::Corrupt);
+        }
+        Ok((sector_count(track - 1) + sector as usize) * SECTOR_LEN)
+    }
+
+    pub fn sector(&self, track: u8, sector: u8) -> Result<&[u8], ErrorKind> {
+        let at = self.offset(track, sector)?;
+        Ok(&self.data[at..at + SECTOR_LEN])
+    }
+
+    pub fn sector_mut(&mut self, track: u8, sector: u8) -> Result<&mut [u8], ErrorKind> {
+        let at = self.offset(track, sector)?;
+        Ok(&mut self.data[at..at + SECTOR_LEN])
+    }
+
+    /// The disk name with its padding removed.
+    pub fn name(&self) -> &[u8] {
+        let bam = self.sector(DIR_TRACK, 0).unwrap();
+        trim_padded(&bam[0x90..0xa0])
+    }
+
+    /// The sectors of the chain starting at `start`, in order.
+    pub fn chain(&self, start: (u8, u8)) -> Result<Vec<(u8, u8)>, ErrorKind> {
+        let mut seen = alloc::vec![false; sector_count(self.tracks)];
+        let mut out = Vec::new();
+        let (mut track, mut sector) = start;
+        while track != 0 {
+            let at = self.offset(track, sector)?;
+            let index = at / SECTOR_LEN;
+            if core::mem::replace(&mut seen[index], true) {
+                return Err(ErrorKind::Corrupt);
+            }
+            out.push((track, sector));
+            (track, sector) = (self.data[at], self.data[at + 1]);
+        }
+        Ok(out)
+    }
+
+    /// Every file in the directory, skipping scratched slots.
+    pub fn directory(&self) -> Result<Vec<DirEntry>, ErrorKind> {
+        let mut out = Vec::new();
+        for slot in self.dir_slots()? {
+            let e = &self.data[slot..slot + 32];
+            if e[2] == 0 {
+                continue;
+            }
+            out.push(DirEntry {
+                name: trim_padded(&e[5..0x15]).to_vec(),
+                file_type: FileType::from_bits(e[2]),
+                closed: e[2] & 0x80 != 0,
+                locked: e[2] & 0x40 != 0,
+                start: (e[3], e[4]),
+                blocks: u16::from_le_bytes([e[0x1e], e[0x1f]]),
+            });
+        }
+        Ok(out)
+    }
+
+    /// Offsets of every 32-byte directory slot, in directory order.
+    fn dir_slots(&self) -> Result<Vec<usize>, ErrorKind> {
+        let bam = self.sector(DIR_TRACK, 0)?;
+        let mut slots = Vec::new();
+        for (t, s) in self.chain((bam[0], bam[1]))? {
+            let at = self.offset(t, s)?;
+            slots.extend((0..8).map(|i| at + 32 * i));
+        }
+        Ok(slots)
+    }
+
+    /// The contents of the file starting at `start`.
+    pub fn read_chain(&self, start: (u8, u8)) -> Result<Vec<u8>, ErrorKind> {
+        let mut out = Vec::new();
+        for (t, s) in self.chain(start)? {
+            let b = self.sector(t, s)?;
+            let end = if b[0] == 0 {
+                // last sector: byte 1 is the index of the last byte used
+                (b[1] as usize + 1).max(2)
+            } else {
+                SECTOR_LEN
+            };
+            out.extend_from_slice(&b[2..end]);
+        }
+        Ok(out)
+    }
+
+    /// The first file called `name`.
+    pub fn find(&self, name: &[u8]) -> Result<DirEntry, ErrorKind> {
+        self.directory()?
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or(ErrorKind::NotFound)
+    }
+
+    pub fn read_file(&self, name: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+        self.read_chain(self.find(name)?.start)
+    }
+
+    pub fn extract_prg(&self, name: &[u8]) -> Result<Prg, ErrorKind> {
+        Prg::from_bytes(&self.read_file(name)?)
+    }
+
+    pub fn insert_prg(&mut self, name: &[u8], prg: &Prg) -> Result<(), ErrorKind> {
+        self.insert_file(name, FileType::Prg, &prg.to_bytes())
+    }
+
+    /// Write `data` as a new closed file, allocating its sectors and a
+    /// directory slot in the BAM. On [`ErrorKind::Full`] the disk is left
+    /// unchanged.
+    pub fn insert_file(
+        &mut self,
+        name: &[u8],
+        file_type: FileType,
+        data: &[u8],
+    ) -> Result<(), ErrorKind> {
+        let backup = self.data.clone();
+        let res = self.try_insert_file(name, file_type, data);
+        if res.is_err() {
+            self.data = backup;
+        }
+        res
+    }
+
+    fn try_insert_file(
+        &mut self,
+        name: &[u8],
+        file_type: FileType,
+        data: &[u8],
+    ) -> Result<(), ErrorKind> {
+        let slot = match self
+            .dir_slots()?
+            .into_iter()
+            .find(|&s| self.data[s + 2] == 0)
+        {
+            Some(slot) => slot,
+            None => self.extend_directory()?,
+        };
+
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            alloc::vec![&[][..]]
+        } else {
+            data.chunks(SECTOR_DATA).collect()
+        };
+        let mut sectors = Vec::with_capacity(chunks.len());
+        let mut last = None;
+        for _ in &chunks {
+            let ts = self.next_free(last).ok_or(ErrorKind::Full)?;
+            self.allocate_sector(ts.0, ts.1);
+            sectors.push(ts);
+            last = Some(ts);
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            let (t, s) = sectors[i];
+            let link = match sectors.get(i + 1) {
+                Some(&next) => next,
+                None => (0, chunk.len() as u8 + 1),
+            };
+            let b = self.sector_mut(t, s)?;
+            b.fill(0);
+            b[..2].copy_from_slice(&[link.0, link.1]);
+            b[2..2 + chunk.len()].copy_from_slice(chunk);
+        }
+
+        let e = &mut self.data[slot + 2..slot + 32];
+        e.fill(0);
+        e[0] = 0x80 | file_type.bits();
+        e[1..3].copy_from_slice(&[sectors[0].0, sectors[0].1]);
+        write_padded(&mut e[3..0x13], name);
+        e[0x1c..0x1e].copy_from_slice(&(sectors.len() as u16).to_le_bytes());
+        Ok(())
+    }
+
+    /// Add a sector to the end of the directory chain, returning its first
+    /// slot.
+    fn extend_directory(&mut self) -> Result<usize, ErrorKind> {
+        let bam = self.sector(DIR_TRACK, 0)?;
+        let &(t, s) = self
+            .chain((bam[0], bam[1]))?
+            .last()
+            .ok_or(ErrorKind::Corrupt)?;
+        let n = sectors_per_track(DIR_TRACK);
+        let (nt, ns) = (0..n)
+            .map(|i| (DIR_TRACK, (s + 3 + i) % n))
+            .find(|&(t, s)| self.is_free(t, s))
+            .ok_or(ErrorKind::Full)?;
+        self.allocate_sector(nt, ns);
+        self.sector_mut(t, s)?[..2].copy_from_slice(&[nt, ns]);
+        let b = self.sector_mut(nt, ns)?;
+        b.fill(0);
+        b[1] = 0xff;
+        self.offset(nt, ns)
+    }
+
+    fn bam_entry(&self, track: u8) -> Option<usize> {
+        (1..=35)
+            .contains(&track)
+            .then(|| self.offset(DIR_TRACK, 0).unwrap() + 4 * track as usize)
+    }
+
+    /// Whether the BAM marks a sector as free.
+    pub fn is_free(&self, track: u8, sector: u8) -> bool {
+        let Some(at) = self.bam_entry(track) else {
+            return false;
+        };
+        sector < sectors_per_track(track)
+            && self.data[at + 1 + sector as usize / 8] & (1 << (sector % 8)) != 0
+    }
+
+    /// Free sectors left on the disk, from the BAM, leaving out the
+    /// directory track as DOS does.
+    pub fn blocks_free(&self) -> u32 {
+        (1..=35u8)
+            .filter(|&t| t != DIR_TRACK)
+            .map(|t| self.data[self.bam_entry(t).unwrap()] as u32)
+            .sum()
+    }
+
+    fn allocate_sector(&mut self, track: u8, sector: u8) {
+        if !self.is_free(track, sector) {
+            return;
+        }
+        let at = self.bam_entry(track).unwrap();
+        self.data[at] -= 1;
+        self.data[at + 1 + sector as usize / 8] &= !(1 << (sector % 8));
+    }
+
+    /// Pick the next sector for a file: after `last` with the usual
+    /// interleave on the same track if possible, otherwise on the free track
+    /// nearest the directory.
+    fn next_free(&self, last: Option<(u8, u8)>) -> Option<(u8, u8)> {
+        let search = |t: u8, from: u8| {
+            let n = sectors_per_track(t);
+            (0..n)
+                .map(|i| (t, (from + i) % n))
+                .find(|&(t, s)| self.is_free(t, s))
+        };
+        if let Some((t, s)) = last
+            && let Some(found) = search(t, (s + INTERLEAVE) % sectors_per_track(t))
+        {
+            return Some(found);
+        }
+        (1..DIR_TRACK)
+            .flat_map(|d| [DIR_TRACK - d, DIR_TRACK + d])
+            .filter(|&t| (1..=35).contains(&t))
+            .find_map(|t| search(t, 0))
+    }
+}
+
+/// The `.d64` disk image format, taking the whole remaining byte stream as
+/// one image.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct D64Parser;
+
+impl<T: From<D64>, Err: From<ErrorKind>> FileParser<T, Err> for D64Parser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let disk = D64::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(disk)))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        let disk = D64::from_bytes(bytes).ok()?;
+        let bam = disk.sector(DIR_TRACK, 0).ok()?;
+        let mut confidence = 40;
+        if bam[0] == DIR_TRACK && bam[2] == b'A' {
+            confidence += 40;
+        }
+        if extension == Some("d64") {
+            confidence += 20;
+        }
+        Some(confidence)
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("1541 disk image"))
+    }
+}
+
+impl<T: TryAsRef<D64>, Err: From<ErrorKind>> FileWriter<T, Err> for D64Parser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let disk = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend_from_slice(disk.as_bytes());
+        Ok(())
+    }
+}
+
+fn sectors_per_track(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+/// Sectors on tracks 1 to `tracks`.
+fn sector_count(tracks: u8) -> usize {
+    (1..=tracks).map(|t| sectors_per_track(t) as usize).sum()
+}
+
+fn write_padded(field: &mut [u8], name: &[u8]) {
+    field.fill(PAD);
+    let n = name.len().min(field.len());
+    field[..n].copy_from_slice(&name[..n]);
+}
+
+fn trim_padded(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == PAD).unwrap_or(field.len());
+    &field[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn prg_round_trip() {
+        let prg = Prg::from_bytes(&[0x01, 0x08, 1, 2, 3]).unwrap();
+        assert_eq!(prg.load_address, 0x0801);
+        let image = prg.to_image();
+        assert_eq!(image.get(0x0803), Some(3));
+        assert_eq!(Prg::from_image(&image).unwrap(), prg);
+        assert_eq!(prg.to_bytes(), [0x01, 0x08, 1, 2, 3]);
+    }
+    #[test]
+    fn malformed_prg() {
+        assert_eq!(Prg::from_bytes(&[0x01]).unwrap_err(), ErrorKind::Truncated);
+        assert_eq!(
+            Prg::from_image(&MemoryImage::new()).unwrap_err(),
+            ErrorKind::Truncated
+        );
+        let past_end = MemoryImage::from_bytes(0xffff, &[1, 2]);
+        assert_eq!(Prg::from_image(&past_end).unwrap_err(), ErrorKind::Format);
+    }
+    #[test]
+    fn d64_round_trip() {
+        let mut disk = D64::new(b"TEST DISK", *b"01");
+        assert_eq!(disk.name(), b"TEST DISK");
+        assert_eq!(disk.blocks_free(), 664);
+        let big = Prg {
+            load_address: 0x1000,
+            data: (0..1000u32).map(|i| i as u8).collect(),
+        };
+        disk.insert_prg(b"BIG", &big).unwrap();
+        disk.insert_file(b"EMPTY", FileType::Seq, &[]).unwrap();
+        // more than the 8 entries one directory sector holds
+        for i in 0..10u8 {
+            disk.insert_file(&[b'F', b'0' + i], FileType::Usr, &[i])
+                .unwrap();
+        }
+        let back = D64::from_bytes(disk.as_bytes()).unwrap();
+        assert_eq!(back, disk);
+        let dir = back.directory().unwrap();
+        assert_eq!(dir.len(), 12);
+        assert_eq!((dir[0].blocks, dir[0].file_type), (4, FileType::Prg));
+        assert_eq!(back.extract_prg(b"BIG").unwrap(), big);
+        assert_eq!(back.read_file(b"EMPTY").unwrap(), []);
+        assert_eq!(back.read_file(b"F9").unwrap(), [9]);
+        assert_eq!(back.blocks_free(), 664 - 4 - 1 - 10);
+    }
+    #[test]
+    fn malformed_d64() {
+        assert_eq!(D64::from_bytes(&[0; 1000]).unwrap_err(), ErrorKind::Format);
+        let mut disk = D64::new(b"LOOP", *b"01");
+        disk.insert_file(b"A", FileType::Seq, &[0; 600]).unwrap();
+        assert_eq!(disk.read_file(b"B").unwrap_err(), ErrorKind::NotFound);
+        // a chain that links back to its own sector
+        let start = disk.find(b"A").unwrap().start;
+        disk.sector_mut(start.0, start.1).unwrap()[..2].copy_from_slice(&[start.0, start.1]);
+        assert_eq!(disk.read_file(b"A").unwrap_err(), ErrorKind::Corrupt);
+        let before = disk.clone();
+        assert_eq!(
+            disk.insert_file(b"HUGE", FileType::Seq, &vec![0; 700 * 254])
+                .unwrap_err(),
+            ErrorKind::Full
+        );
+        assert_eq!(disk, before);
+    }
+}
@@ -0,0 +1,1318 @@
+use crate::image::MemoryImage;
+use crate::listing::{Symbol, SymbolKind, SymbolTable};
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, TryAsRef};
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+const MAGIC: &[u8] = b"\x7fELF";
+
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const EM_386: u16 = 3;
+pub const EM_68K: u16 = 4;
+pub const EM_MIPS: u16 = 8;
+pub const EM_ARM: u16 = 40;
+pub const EM_X86_64: u16 = 62;
+pub const EM_RISCV: u16 = 243;
+/// As used by llvm-mos.
+pub const EM_MOS: u16 = 6502;
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHT_DYNSYM: u32 = 11;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_INFO_LINK: u64 = 0x40;
+
+pub const SHN_UNDEF: u16 = 0;
+/// Section indices from here on are reserved for `SHN_*` values.
+pub const SHN_LORESERVE: u16 = 0xff00;
+pub const SHN_ABS: u16 = 0xfff1;
+pub const SHN_COMMON: u16 = 0xfff2;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+/// Where executables are placed relative to page boundaries.
+const PAGE: u64 = 0x1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// A section, with its header fields and contents.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ElfSection {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    /// Where the contents sit in the file, or 0 to let the writer place
+    /// them after everything else.
+    pub offset: u64,
+    /// `sh_size`. The writer only keeps it for `SHT_NOBITS` sections, which
+    /// have no contents in the file, and uses the length of `data`
+    /// otherwise.
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub align: u64,
+    pub entsize: u64,
+    pub data: Vec<u8>,
+}
+
+impl ElfSection {
+    pub fn new(name: &str, kind: u32, flags: u64, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+            flags,
+            addr: 0,
+            offset: 0,
+            size: data.len() as u64,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+            data,
+        }
+    }
+
+    /// Whether `addr` falls within the contents, placed at the section's
+    /// address.
+    fn holds(&self, addr: u64) -> bool {
+        addr.checked_sub(self.addr)
+            .is_some_and(|at| at < self.data.len() as u64)
+    }
+
+    fn file_len(&self) -> u64 {
+        match self.kind {
+            SHT_NOBITS => 0,
+            _ => self.data.len() as u64,
+        }
+    }
+}
+
+/// A program header, written as it stands.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct ElfSegment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ElfSymbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    /// `STB_*`, the high nibble of `st_info`.
+    pub binding: u8,
+    /// `STT_*`, the low nibble of `st_info`.
+    pub kind: u8,
+    pub other: u8,
+    /// Index of the section the symbol is defined in, or a `SHN_*` value.
+    pub shndx: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct ElfRelocation {
+    pub offset: u64,
+    /// Index into the linked symbol table.
+    pub symbol: u32,
+    /// Machine-specific relocation type.
+    pub kind: u32,
+    /// The explicit addend of `SHT_RELA` entries; `SHT_REL` entries take it
+    /// from the relocated field.
+    pub addend: Option<i64>,
+}
+
+/// Fixed-width field reads in the file's class and byte order.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    class: ElfClass,
+    endian: Endian,
+}
+
+impl<'a> Reader<'a> {
+    fn at(&self, pos: usize) -> Self {
+        Reader { pos, ..*self }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ErrorKind> {
+        let bytes = self
+            .bytes
+            .get(self.pos..)
+            .and_then(|b| b.first_chunk::<N>())
+            .ok_or(ErrorKind::Truncated)?;
+        self.pos += N;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ErrorKind> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ErrorKind> {
+        let b = self.take()?;
+        Ok(match self.endian {
+            Endian::Little => u16::from_le_bytes(b),
+            Endian::Big => u16::from_be_bytes(b),
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, ErrorKind> {
+        let b = self.take()?;
+        Ok(match self.endian {
+            Endian::Little => u32::from_le_bytes(b),
+            Endian::Big => u32::from_be_bytes(b),
+        })
+    }
+
+    fn u64(&mut self) -> Result<u64, ErrorKind> {
+        let b = self.take()?;
+        Ok(match self.endian {
+            Endian::Little => u64::from_le_bytes(b),
+            Endian::Big => u64::from_be_bytes(b),
+        })
+    }
+
+    /// An address-sized field.
+    fn word(&mut self) -> Result<u64, ErrorKind> {
+        match self.class {
+            ElfClass::Elf32 => self.u32().map(u64::from),
+            ElfClass::Elf64 => self.u64(),
+        }
+    }
+
+    /// A file range given by an offset and length.
+    fn slice(&self, offset: u64, len: u64) -> Result<&'a [u8], ErrorKind> {
+        let start = usize::try_from(offset).map_err(|_| ErrorKind::Truncated)?;
+        let len = usize::try_from(len).map_err(|_| ErrorKind::Truncated)?;
+        self.bytes
+            .get(start..)
+            .and_then(|b| b.get(..len))
+            .ok_or(ErrorKind::Truncated)
+    }
+}
+
+/// Fixed-width field writes in the file's class and byte order.
+struct Writer<'a> {
+    out: &'a mut Vec<u8>,
+    class: ElfClass,
+    endian: Endian,
+}
+
+impl Writer<'_> {
+    fn u8(&mut self, v: u8) {
+        self.out.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.out.extend(match self.endian {
+            Endian::Little => v.to_le_bytes(),
+            Endian::Big => v.to_be_bytes(),
+        });
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.out.extend(match self.endian {
+            Endian::Little => v.to_le_bytes(),
+            Endian::Big => v.to_be_bytes(),
+        });
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.out.extend(match self.endian {
+            Endian::Little => v.to_le_bytes(),
+            Endian::Big => v.to_be_bytes(),
+        });
+    }
+
+    /// An address-sized field; values that do not fit an ELF32 field are
+    /// [`ErrorKind::Format`] errors.
+    fn word(&mut self, v: u64) -> Result<(), ErrorKind> {
+        match self.class {
+            ElfClass::Elf32 => self.u32(u32::try_from(v).map_err(|_| ErrorKind::Format)?),
+            ElfClass::Elf64 => self.u64(v),
+        }
+        Ok(())
+    }
+}
+
+/// The NUL-terminated string at `offset` in a string table.
+fn string_at(table: &[u8], offset: u32) -> Result<String, ErrorKind> {
+    let tail = table.get(offset as usize..).ok_or(ErrorKind::Corrupt)?;
+    let end = tail
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(ErrorKind::Corrupt)?;
+    Ok(String::from_utf8_lossy(&tail[..end]).into_owned())
+}
+
+/// Offsets of `names` in a string table based on `table`, appending those
+/// it does not already hold. Names may share a suffix with a longer one.
+fn intern(table: &mut Vec<u8>, names: &[&str]) -> Vec<u32> {
+    if table.is_empty() {
+        table.push(0);
+    }
+    names
+        .iter()
+        .map(|name| {
+            let mut needle = name.as_bytes().to_vec();
+            needle.push(0);
+            let found = table.windows(needle.len()).position(|w| w == needle);
+            let offset = found.unwrap_or_else(|| {
+                table.extend_from_slice(&needle);
+                table.len() - needle.len()
+            });
+            offset as u32
+        })
+        .collect()
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    value.next_multiple_of(align.max(1))
+}
+
+/// The end of `len` bytes from `start`, which in a well-formed file cannot
+/// run past the end of the address space.
+fn end_of(start: u64, len: u64) -> Result<u64, ErrorKind> {
+    start.checked_add(len).ok_or(ErrorKind::Corrupt)
+}
+
+/// An ELF object, executable or shared object.
+///
+/// Section 0 is the null section. Section and program header tables are
+/// rebuilt on writing, and section contents stay at their offsets where
+/// they still fit, so unchanged files keep their layout.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Elf {
+    pub class: ElfClass,
+    pub endian: Endian,
+    pub os_abi: u8,
+    pub abi_version: u8,
+    /// `ET_*`.
+    pub kind: u16,
+    /// `EM_*`.
+    pub machine: u16,
+    pub entry: u64,
+    pub flags: u32,
+    pub sections: Vec<ElfSection>,
+    pub segments: Vec<ElfSegment>,
+    /// Index of the section holding section names, rewritten from the
+    /// names on writing.
+    pub shstrndx: u16,
+}
+
+impl Elf {
+    /// An empty file holding only the null section and `.shstrtab`.
+    pub fn new(class: ElfClass, endian: Endian, kind: u16, machine: u16) -> Self {
+        let mut null = ElfSection::new("", SHT_NULL, 0, Vec::new());
+        null.align = 0;
+        Self {
+            class,
+            endian,
+            os_abi: 0,
+            abi_version: 0,
+            kind,
+            machine,
+            entry: 0,
+            flags: 0,
+            sections: vec![null, ElfSection::new(".shstrtab", SHT_STRTAB, 0, vec![0])],
+            segments: Vec::new(),
+            shstrndx: 1,
+        }
+    }
+
+    fn header_len(class: ElfClass) -> (u64, u64, u64) {
+        // file header, program header and section header sizes
+        match class {
+            ElfClass::Elf32 => (52, 32, 40),
+            ElfClass::Elf64 => (64, 56, 64),
+        }
+    }
+
+    /// Size of an address-sized field, and the alignment of the tables
+    /// made of them.
+    fn word_len(&self) -> u64 {
+        match self.class {
+            ElfClass::Elf32 => 4,
+            ElfClass::Elf64 => 8,
+        }
+    }
+
+    fn symbol_len(&self) -> u64 {
+        match self.class {
+            ElfClass::Elf32 => 16,
+            ElfClass::Elf64 => 24,
+        }
+    }
+
+    fn relocation_len(&self, rela: bool) -> u64 {
+        match (self.class, rela) {
+            (ElfClass::Elf32, false) => 8,
+            (ElfClass::Elf32, true) => 12,
+            (ElfClass::Elf64, false) => 16,
+            (ElfClass::Elf64, true) => 24,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ErrorKind::BadMagic);
+        }
+        let ident = bytes.get(..16).ok_or(ErrorKind::Truncated)?;
+        let class = match ident[4] {
+            1 => ElfClass::Elf32,
+            2 => ElfClass::Elf64,
+            _ => return Err(ErrorKind::Unsupported),
+        };
+        let endian = match ident[5] {
+            1 => Endian::Little,
+            2 => Endian::Big,
+            _ => return Err(ErrorKind::Unsupported),
+        };
+        let r = Reader {
+            bytes,
+            pos: 16,
+            class,
+            endian,
+        };
+        let mut h = r.at(16);
+        let kind = h.u16()?;
+        let machine = h.u16()?;
+        h.u32()?;
+        let entry = h.word()?;
+        let phoff = h.word()?;
+        let shoff = h.word()?;
+        let flags = h.u32()?;
+        h.u16()?;
+        let phentsize = h.u16()? as u64;
+        let phnum = h.u16()? as u64;
+        let shentsize = h.u16()? as u64;
+        let shnum = h.u16()? as u64;
+        let shstrndx = h.u16()?;
+        let (_, ph_len, sh_len) = Self::header_len(class);
+        if (phnum > 0 && phentsize < ph_len) || (shnum > 0 && shentsize < sh_len) {
+            return Err(ErrorKind::Corrupt);
+        }
+        if shnum == 0 && shoff != 0 {
+            // more than SHN_LORESERVE sections, counted in section 0
+            return Err(ErrorKind::Unsupported);
+        }
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let pos = phoff + i * phentsize;
+            let mut p = r.at(usize::try_from(pos).map_err(|_| ErrorKind::Truncated)?);
+            let kind = p.u32()?;
+            let segment = match class {
+                ElfClass::Elf32 => {
+                    let (offset, vaddr, paddr, filesz, memsz) =
+                        (p.word()?, p.word()?, p.word()?, p.word()?, p.word()?);
+                    let flags = p.u32()?;
+                    ElfSegment {
+                        kind,
+                        flags,
+                        offset,
+                        vaddr,
+                        paddr,
+                        filesz,
+                        memsz,
+                        align: p.word()?,
+                    }
+                }
+                ElfClass::Elf64 => ElfSegment {
+                    kind,
+                    flags: p.u32()?,
+                    offset: p.word()?,
+                    vaddr: p.word()?,
+                    paddr: p.word()?,
+                    filesz: p.word()?,
+                    memsz: p.word()?,
+                    align: p.word()?,
+                },
+            };
+            segments.push(segment);
+        }
+
+        let mut headers = Vec::new();
+        for i in 0..shnum {
+            let pos = shoff + i * shentsize;
+            let mut s = r.at(usize::try_from(pos).map_err(|_| ErrorKind::Truncated)?);
+            let name = s.u32()?;
+            let mut section = ElfSection::new("", s.u32()?, s.word()?, Vec::new());
+            section.addr = s.word()?;
+            section.offset = s.word()?;
+            section.size = s.word()?;
+            section.link = s.u32()?;
+            section.info = s.u32()?;
+            section.align = s.word()?;
+            section.entsize = s.word()?;
+            if section.kind != SHT_NOBITS && section.kind != SHT_NULL {
+                section.data = r.slice(section.offset, section.size)?.to_vec();
+            }
+            headers.push((name, section));
+        }
+        let names = match headers.get(shstrndx as usize) {
+            Some((_, table)) if shstrndx != 0 => table.data.clone(),
+            _ => Vec::new(),
+        };
+        let sections = headers
+            .into_iter()
+            .map(|(name, mut section)| {
+                if !names.is_empty() {
+                    section.name = string_at(&names, name)?;
+                }
+                Ok(section)
+            })
+            .collect::<Result<_, ErrorKind>>()?;
+
+        Ok(Self {
+            class,
+            endian,
+            os_abi: ident[7],
+            abi_version: ident[8],
+            kind,
+            machine,
+            entry,
+            flags,
+            sections,
+            segments,
+            shstrndx,
+        })
+    }
+
+    /// Write the whole file.
+    ///
+    /// Section contents go back to their offsets unless they would overlap
+    /// the headers or contents placed before them, in which case they move
+    /// to the end of the file along with the segments loading them; contents
+    /// without an offset go there too. Values too wide for an ELF32 field
+    /// are [`ErrorKind::Format`] errors.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorKind> {
+        let (eh_len, ph_len, sh_len) = Self::header_len(self.class);
+        let shnum = u16::try_from(self.sections.len()).map_err(|_| ErrorKind::Format)?;
+        let phnum = u16::try_from(self.segments.len()).map_err(|_| ErrorKind::Format)?;
+        let phoff = if phnum > 0 { eh_len } else { 0 };
+
+        let mut name_table = match self.sections.get(self.shstrndx as usize) {
+            Some(table) if self.shstrndx != 0 => table.data.clone(),
+            _ => Vec::new(),
+        };
+        let names: Vec<&str> = self.sections.iter().map(|s| s.name.as_str()).collect();
+        let name_offsets = if self.shstrndx != 0 {
+            intern(&mut name_table, &names)
+        } else {
+            vec![0; names.len()]
+        };
+        let contents = |i: usize| {
+            if i == self.shstrndx as usize && i != 0 {
+                &name_table[..]
+            } else {
+                &self.sections[i].data[..]
+            }
+        };
+
+        // sections keeping their offsets claim them first, so that the rest
+        // can't be laid out over them
+        let lens: Vec<u64> = (0..self.sections.len())
+            .map(|i| match self.sections[i].kind {
+                SHT_NOBITS | SHT_NULL => 0,
+                _ => contents(i).len() as u64,
+            })
+            .collect();
+        let mut used = vec![(0, eh_len + ph_len * phnum as u64)];
+        let mut offsets: Vec<Option<u64>> = Vec::with_capacity(self.sections.len());
+        for (section, &len) in self.sections.iter().zip(&lens) {
+            let end = end_of(section.offset, len)?;
+            let fits = used.iter().all(|&(s, e)| end <= s || section.offset >= e);
+            let offset = (len == 0 || (section.offset != 0 && fits)).then_some(section.offset);
+            if len > 0 && offset.is_some() {
+                used.push((section.offset, end));
+            }
+            offsets.push(offset);
+        }
+
+        // the rest go after everything else, and segments loading moved
+        // contents follow them
+        let mut end = used.iter().map(|&(_, e)| e).max().unwrap_or(0);
+        let mut segments = self.segments.clone();
+        let offsets: Vec<u64> = (0..self.sections.len())
+            .map(|i| {
+                if let Some(offset) = offsets[i] {
+                    return Ok(offset);
+                }
+                let (old, len) = (self.sections[i].offset, lens[i]);
+                let old_end = end_of(old, len)?;
+                let mut moved = Vec::new();
+                for (j, p) in segments.iter().enumerate() {
+                    if old != 0
+                        && p.filesz > 0
+                        && p.offset >= old
+                        && end_of(p.offset, p.filesz)? <= old_end
+                    {
+                        moved.push(j);
+                    }
+                }
+                let align = moved
+                    .iter()
+                    .map(|&j| segments[j].align)
+                    .fold(self.sections[i].align.max(1), u64::max);
+                let offset = end
+                    .checked_next_multiple_of(align)
+                    .and_then(|at| at.checked_add(old % align))
+                    .ok_or(ErrorKind::Corrupt)?;
+                for j in moved {
+                    segments[j].offset = segments[j].offset - old + offset;
+                }
+                end = end_of(offset, len)?;
+                Ok(offset)
+            })
+            .collect::<Result<_, ErrorKind>>()?;
+        let shoff = end
+            .checked_next_multiple_of(self.word_len())
+            .ok_or(ErrorKind::Corrupt)?;
+
+        let mut out = vec![0; shoff as usize];
+        for (i, (&offset, &len)) in offsets.iter().zip(&lens).enumerate() {
+            if len > 0 {
+                out[offset as usize..(offset + len) as usize].copy_from_slice(contents(i));
+            }
+        }
+
+        let mut head = Vec::with_capacity(eh_len as usize);
+        let mut w = Writer {
+            out: &mut head,
+            class: self.class,
+            endian: self.endian,
+        };
+        w.out.extend_from_slice(MAGIC);
+        w.u8(match self.class {
+            ElfClass::Elf32 => 1,
+            ElfClass::Elf64 => 2,
+        });
+        w.u8(match self.endian {
+            Endian::Little => 1,
+            Endian::Big => 2,
+        });
+        w.u8(1);
+        w.u8(self.os_abi);
+        w.u8(self.abi_version);
+        w.out.resize(16, 0);
+        w.u16(self.kind);
+        w.u16(self.machine);
+        w.u32(1);
+        w.word(self.entry)?;
+        w.word(phoff)?;
+        w.word(if shnum > 0 { shoff } else { 0 })?;
+        w.u32(self.flags);
+        w.u16(eh_len as u16);
+        w.u16(ph_len as u16);
+        w.u16(phnum);
+        w.u16(sh_len as u16);
+        w.u16(shnum);
+        w.u16(self.shstrndx);
+        for p in &segments {
+            w.u32(p.kind);
+            if self.class == ElfClass::Elf64 {
+                w.u32(p.flags);
+            }
+            w.word(p.offset)?;
+            w.word(p.vaddr)?;
+            w.word(p.paddr)?;
+            w.word(p.filesz)?;
+            w.word(p.memsz)?;
+            if self.class == ElfClass::Elf32 {
+                w.u32(p.flags);
+            }
+            w.word(p.align)?;
+        }
+        out[..head.len()].copy_from_slice(&head);
+
+        let mut w = Writer {
+            out: &mut out,
+            class: self.class,
+            endian: self.endian,
+        };
+        for (i, section) in self.sections.iter().enumerate() {
+            let size = match section.kind {
+                SHT_NOBITS | SHT_NULL => section.size,
+                _ => lens[i],
+            };
+            w.u32(name_offsets[i]);
+            w.u32(section.kind);
+            w.word(section.flags)?;
+            w.word(section.addr)?;
+            w.word(offsets[i])?;
+            w.word(size)?;
+            w.u32(section.link);
+            w.u32(section.info);
+            w.word(section.align)?;
+            w.word(section.entsize)?;
+        }
+        Ok(out)
+    }
+
+    /// Index of the first section named `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.sections.iter().position(|s| s.name == name)
+    }
+
+    fn reader<'a>(&self, bytes: &'a [u8]) -> Reader<'a> {
+        Reader {
+            bytes,
+            pos: 0,
+            class: self.class,
+            endian: self.endian,
+        }
+    }
+
+    /// The entries of a `SHT_SYMTAB` or `SHT_DYNSYM` section, including the
+    /// null symbol at index 0.
+    pub fn symbols(&self, index: usize) -> Result<Vec<ElfSymbol>, ErrorKind> {
+        let section = self.sections.get(index).ok_or(ErrorKind::NotFound)?;
+        if !matches!(section.kind, SHT_SYMTAB | SHT_DYNSYM) {
+            return Err(ErrorKind::WrongValue);
+        }
+        let strings = &self
+            .sections
+            .get(section.link as usize)
+            .ok_or(ErrorKind::Corrupt)?
+            .data;
+        let mut r = self.reader(&section.data);
+        let count = section.data.len() as u64 / self.symbol_len();
+        (0..count)
+            .map(|_| {
+                let name = r.u32()?;
+                let (value, size, info, other, shndx);
+                match self.class {
+                    ElfClass::Elf32 => {
+                        value = r.word()?;
+                        size = r.word()?;
+                        info = r.u8()?;
+                        other = r.u8()?;
+                        shndx = r.u16()?;
+                    }
+                    ElfClass::Elf64 => {
+                        info = r.u8()?;
+                        other = r.u8()?;
+                        shndx = r.u16()?;
+                        value = r.word()?;
+                        size = r.word()?;
+                    }
+                }
+                Ok(ElfSymbol {
+                    name: string_at(strings, name)?,
+                    value,
+                    size,
+                    binding: info >> 4,
+                    kind: info & 0x0f,
+                    other,
+                    shndx,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace the entries of a symbol table section, writing their names
+    /// into its linked string table. Local symbols must come first, as
+    /// `sh_info` counts them; otherwise this is an [`ErrorKind::Format`]
+    /// error.
+    pub fn set_symbols(&mut self, index: usize, symbols: &[ElfSymbol]) -> Result<(), ErrorKind> {
+        let section = self.sections.get(index).ok_or(ErrorKind::NotFound)?;
+        if !matches!(section.kind, SHT_SYMTAB | SHT_DYNSYM) {
+            return Err(ErrorKind::WrongValue);
+        }
+        let link = section.link as usize;
+        if link == 0 || link >= self.sections.len() {
+            return Err(ErrorKind::Corrupt);
+        }
+        let locals = symbols
+            .iter()
+            .take_while(|s| s.binding == STB_LOCAL)
+            .count();
+        if symbols[locals..].iter().any(|s| s.binding == STB_LOCAL) {
+            return Err(ErrorKind::Format);
+        }
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        let offsets = intern(&mut self.sections[link].data, &names);
+
+        let mut data = Vec::with_capacity(symbols.len() * self.symbol_len() as usize);
+        let mut w = Writer {
+            out: &mut data,
+            class: self.class,
+            endian: self.endian,
+        };
+        for (s, name) in symbols.iter().zip(offsets) {
+            let info = (s.binding << 4) | (s.kind & 0x0f);
+            w.u32(name);
+            match self.class {
+                ElfClass::Elf32 => {
+                    w.word(s.value)?;
+                    w.word(s.size)?;
+                    w.u8(info);
+                    w.u8(s.other);
+                    w.u16(s.shndx);
+                }
+                ElfClass::Elf64 => {
+                    w.u8(info);
+                    w.u8(s.other);
+                    w.u16(s.shndx);
+                    w.word(s.value)?;
+                    w.word(s.size)?;
+                }
+            }
+        }
+        let entsize = self.symbol_len();
+        let section = &mut self.sections[index];
+        section.data = data;
+        section.info = locals as u32;
+        section.entsize = entsize;
+        Ok(())
+    }
+
+    /// The entries of a `SHT_REL` or `SHT_RELA` section.
+    pub fn relocations(&self, index: usize) -> Result<Vec<ElfRelocation>, ErrorKind> {
+        let section = self.sections.get(index).ok_or(ErrorKind::NotFound)?;
+        let rela = match section.kind {
+            SHT_REL => false,
+            SHT_RELA => true,
+            _ => return Err(ErrorKind::WrongValue),
+        };
+        let mut r = self.reader(&section.data);
+        let count = section.data.len() as u64 / self.relocation_len(rela);
+        (0..count)
+            .map(|_| {
+                let offset = r.word()?;
+                let info = r.word()?;
+                let (symbol, kind) = match self.class {
+                    ElfClass::Elf32 => ((info >> 8) as u32, info as u32 & 0xff),
+                    ElfClass::Elf64 => ((info >> 32) as u32, info as u32),
+                };
+                let addend = match (rela, self.class) {
+                    (false, _) => None,
+                    (true, ElfClass::Elf32) => Some(r.u32()? as i32 as i64),
+                    (true, ElfClass::Elf64) => Some(r.u64()? as i64),
+                };
+                Ok(ElfRelocation {
+                    offset,
+                    symbol,
+                    kind,
+                    addend,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace the entries of a relocation section. Entries must have an
+    /// addend exactly when the section is `SHT_RELA`, and fit the class's
+    /// symbol and type fields; otherwise this is an [`ErrorKind::Format`]
+    /// error.
+    pub fn set_relocations(
+        &mut self,
+        index: usize,
+        relocations: &[ElfRelocation],
+    ) -> Result<(), ErrorKind> {
+        let section = self.sections.get(index).ok_or(ErrorKind::NotFound)?;
+        let rela = match section.kind {
+            SHT_REL => false,
+            SHT_RELA => true,
+            _ => return Err(ErrorKind::WrongValue),
+        };
+        let mut data = Vec::new();
+        let mut w = Writer {
+            out: &mut data,
+            class: self.class,
+            endian: self.endian,
+        };
+        for rel in relocations {
+            if rel.addend.is_some() != rela {
+                return Err(ErrorKind::Format);
+            }
+            w.word(rel.offset)?;
+            match self.class {
+                ElfClass::Elf32 if rel.symbol < 1 << 24 && rel.kind < 1 << 8 => {
+                    w.u32((rel.symbol << 8) | rel.kind)
+                }
+                ElfClass::Elf64 => w.u64(((rel.symbol as u64) << 32) | rel.kind as u64),
+                _ => return Err(ErrorKind::Format),
+            }
+            match (rel.addend, self.class) {
+                (Some(addend), ElfClass::Elf32) => {
+                    w.u32(i32::try_from(addend).map_err(|_| ErrorKind::Format)? as u32)
+                }
+                (Some(addend), ElfClass::Elf64) => w.u64(addend as u64),
+                (None, _) => {}
+            }
+        }
+        let entsize = self.relocation_len(rela);
+        let section = &mut self.sections[index];
+        section.data = data;
+        section.entsize = entsize;
+        Ok(())
+    }
+
+    /// Add `.strtab` and `.symtab` sections holding `symbols`, after the
+    /// null symbol, and return the index of the symbol table.
+    pub fn add_symbols(&mut self, symbols: &[ElfSymbol]) -> Result<usize, ErrorKind> {
+        let strtab = self.sections.len();
+        self.sections
+            .push(ElfSection::new(".strtab", SHT_STRTAB, 0, vec![0]));
+        let mut symtab = ElfSection::new(".symtab", SHT_SYMTAB, 0, Vec::new());
+        symtab.link = strtab as u32;
+        symtab.align = self.word_len();
+        self.sections.push(symtab);
+        let null = ElfSymbol {
+            name: String::new(),
+            value: 0,
+            size: 0,
+            binding: STB_LOCAL,
+            kind: STT_NOTYPE,
+            other: 0,
+            shndx: SHN_UNDEF,
+        };
+        let mut all = Vec::with_capacity(symbols.len() + 1);
+        all.push(null);
+        all.extend_from_slice(symbols);
+        self.set_symbols(strtab + 1, &all)?;
+        Ok(strtab + 1)
+    }
+
+    /// Add a relocation section applying to section `target` with symbols
+    /// from `symtab`, `SHT_RELA` if the entries carry addends, and return
+    /// its index.
+    pub fn add_relocations(
+        &mut self,
+        target: usize,
+        symtab: usize,
+        relocations: &[ElfRelocation],
+    ) -> Result<usize, ErrorKind> {
+        let rela = relocations.first().is_some_and(|r| r.addend.is_some());
+        let target_name = &self.sections.get(target).ok_or(ErrorKind::NotFound)?.name;
+        let (kind, prefix) = if rela {
+            (SHT_RELA, ".rela")
+        } else {
+            (SHT_REL, ".rel")
+        };
+        let mut section = ElfSection::new(
+            &(prefix.to_string() + target_name),
+            kind,
+            SHF_INFO_LINK,
+            Vec::new(),
+        );
+        section.link = symtab as u32;
+        section.info = target as u32;
+        section.align = self.word_len();
+        let index = self.sections.len();
+        self.sections.push(section);
+        if let Err(e) = self.set_relocations(index, relocations) {
+            self.sections.pop();
+            return Err(e);
+        }
+        Ok(index)
+    }
+
+    /// Add a `SHT_PROGBITS` section holding `image` from its first address,
+    /// named after `section` or `.text`, and return its index.
+    ///
+    /// The section's `flags` attribute picks the `SHF_*` flags from `a`
+    /// (alloc), `w` (write) and `x` (execute), and defaults to `ax`. In
+    /// executables and shared objects the contents are placed on their own
+    /// pages with a `PT_LOAD` segment to match, and the section's entry
+    /// becomes the file's if it has none yet. In relocatable objects the
+    /// section is left at address 0 for the linker to place.
+    pub fn add_image(&mut self, section: &Section, image: &MemoryImage) -> usize {
+        let addr = image.start().or(section.org).unwrap_or(0);
+        let flags = section.attr("flags").unwrap_or("ax");
+        let flags = flags.chars().fold(0, |f, c| match c {
+            'a' => f | SHF_ALLOC,
+            'w' => f | SHF_WRITE,
+            'x' => f | SHF_EXECINSTR,
+            _ => f,
+        });
+        let name = section.name.as_deref().unwrap_or(".text");
+        let mut out = ElfSection::new(name, SHT_PROGBITS, flags, image.to_bytes(addr));
+        if self.kind != ET_REL {
+            out.addr = addr;
+        }
+        out.align = if flags & SHF_EXECINSTR != 0 { 4 } else { 1 };
+        if matches!(self.kind, ET_EXEC | ET_DYN) {
+            // the first page stays free for the headers
+            let end = self
+                .sections
+                .iter()
+                .filter(|s| s.offset != 0)
+                .map(|s| s.offset + s.file_len())
+                .fold(PAGE, u64::max);
+            out.offset = align_up(end, PAGE) + addr % PAGE;
+            let mut p_flags = PF_R;
+            if flags & SHF_WRITE != 0 {
+                p_flags |= PF_W;
+            }
+            if flags & SHF_EXECINSTR != 0 {
+                p_flags |= PF_X;
+            }
+            self.segments.push(ElfSegment {
+                kind: PT_LOAD,
+                flags: p_flags,
+                offset: out.offset,
+                vaddr: addr,
+                paddr: addr,
+                filesz: out.data.len() as u64,
+                memsz: out.data.len() as u64,
+                align: PAGE,
+            });
+            if self.entry == 0
+                && let Some(entry) = section.entry
+            {
+                self.entry = entry;
+            }
+        }
+        self.sections.push(out);
+        self.sections.len() - 1
+    }
+
+    /// Add the symbols of `table` as global symbols, placed in the loaded
+    /// section containing their value if there is one. Constants are
+    /// absolute. Returns the index of the new symbol table; a label in a
+    /// section whose index is reserved for `SHN_*` values is an
+    /// [`ErrorKind::Format`] error.
+    ///
+    /// The sections of relocatable objects have no address to place labels
+    /// by, so labels there are [`ErrorKind::Unsupported`]; add them with
+    /// [`Elf::add_symbols`], valued relative to their section, instead.
+    pub fn add_symbol_table(&mut self, table: &SymbolTable) -> Result<usize, ErrorKind> {
+        if self.kind == ET_REL
+            && table
+                .iter()
+                .any(|(_, symbol)| symbol.kind == SymbolKind::Label)
+        {
+            return Err(ErrorKind::Unsupported);
+        }
+        let symbols: Vec<ElfSymbol> = table
+            .iter()
+            .map(|(name, symbol)| {
+                let shndx = match symbol.kind {
+                    SymbolKind::Constant => SHN_ABS,
+                    SymbolKind::Label => match self
+                        .sections
+                        .iter()
+                        .position(|s| s.flags & SHF_ALLOC != 0 && s.holds(symbol.value))
+                    {
+                        Some(i) => u16::try_from(i)
+                            .ok()
+                            .filter(|&i| i < SHN_LORESERVE)
+                            .ok_or(ErrorKind::Format)?,
+                        None => SHN_ABS,
+                    },
+                };
+                Ok(ElfSymbol {
+                    name: name.to_owned(),
+                    value: symbol.value,
+                    size: 0,
+                    binding: STB_GLOBAL,
+                    kind: STT_NOTYPE,
+                    other: 0,
+                    shndx,
+                })
+            })
+            .collect::<Result<_, ErrorKind>>()?;
+        self.add_symbols(&symbols)
+    }
+
+    /// Each loaded section with contents in the file, as a section named
+    /// after it at its address. The one holding the entry point carries it,
+    /// and the `flags` attribute lists `a`, `w` and `x` as for
+    /// [`Elf::add_image`]. Sections of relocatable objects are not placed
+    /// yet, so they get no origin and no entry, and their images start at
+    /// `sh_addr`, normally 0.
+    pub fn image_sections(&self, arch: &str) -> Vec<(Section, MemoryImage)> {
+        self.sections
+            .iter()
+            .filter(|s| s.flags & SHF_ALLOC != 0 && s.kind != SHT_NOBITS && !s.data.is_empty())
+            .map(|s| {
+                let mut section = Section::new(arch);
+                section.name = Some(s.name.clone());
+                if self.kind != ET_REL {
+                    section.org = Some(s.addr);
+                }
+                if self.kind != ET_REL && s.holds(self.entry) {
+                    section.entry = Some(self.entry);
+                }
+                let flags: String = [(SHF_ALLOC, 'a'), (SHF_WRITE, 'w'), (SHF_EXECINSTR, 'x')]
+                    .into_iter()
+                    .filter(|&(f, _)| s.flags & f != 0)
+                    .map(|(_, c)| c)
+                    .collect();
+                section.attrs.insert(String::from("flags"), flags);
+                (section, MemoryImage::from_bytes(s.addr, &s.data))
+            })
+            .collect()
+    }
+
+    /// The defined symbols of the first `SHT_SYMTAB` (or failing that
+    /// `SHT_DYNSYM`) section: absolute symbols as constants and the rest as
+    /// labels. Section and file symbols are left out. In relocatable objects
+    /// label values are offsets into their sections, and come out relative
+    /// to the section's `sh_addr` as in [`Elf::image_sections`].
+    pub fn symbol_table(&self) -> Result<SymbolTable, ErrorKind> {
+        let mut table = SymbolTable::new();
+        let index = self
+            .sections
+            .iter()
+            .position(|s| s.kind == SHT_SYMTAB)
+            .or_else(|| self.sections.iter().position(|s| s.kind == SHT_DYNSYM));
+        let Some(index) = index else {
+            return Ok(table);
+        };
+        for s in self.symbols(index)? {
+            if s.name.is_empty()
+                || matches!(s.kind, STT_SECTION | STT_FILE)
+                || matches!(s.shndx, SHN_UNDEF | SHN_COMMON)
+            {
+                continue;
+            }
+            let (value, kind) = match s.shndx {
+                SHN_ABS => (s.value, SymbolKind::Constant),
+                _ if self.kind == ET_REL => {
+                    let section = self
+                        .sections
+                        .get(s.shndx as usize)
+                        .ok_or(ErrorKind::Corrupt)?;
+                    (s.value.wrapping_add(section.addr), SymbolKind::Label)
+                }
+                _ => (s.value, SymbolKind::Label),
+            };
+            table.define(&s.name, Symbol { value, kind });
+        }
+        Ok(table)
+    }
+}
+
+/// The ELF format, taking the whole remaining byte stream as one file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElfParser;
+
+impl<T: From<Elf>, Err: From<ErrorKind>> FileParser<T, Err> for ElfParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let elf = Elf::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(elf)))
+    }
+    fn probe(&self, bytes: &[u8], _extension: Option<&str>) -> Option<u32> {
+        bytes.starts_with(MAGIC).then_some(100)
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("ELF object or executable"))
+    }
+}
+
+impl<T: TryAsRef<Elf>, Err: From<ErrorKind>> FileWriter<T, Err> for ElfParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let elf = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend(elf.to_bytes()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    fn label(value: u64) -> Symbol {
+        Symbol {
+            value,
+            kind: SymbolKind::Label,
+        }
+    }
+
+    #[test]
+    fn executable_round_trip() {
+        let mut exe = Elf::new(ElfClass::Elf64, Endian::Little, ET_EXEC, EM_X86_64);
+        let mut text = Section::new("x86");
+        text.entry = Some(0x401000);
+        exe.add_image(&text, &MemoryImage::from_bytes(0x401000, &[0x0f, 0x05]));
+        // symbol tables without offsets must not take the next image's place
+        let mut table = SymbolTable::new();
+        for i in 0..400 {
+            table.define(&format!("a_rather_long_label_{i}"), label(0x401000));
+        }
+        table.define(
+            "K",
+            Symbol {
+                value: 5,
+                kind: SymbolKind::Constant,
+            },
+        );
+        exe.add_symbol_table(&table).unwrap();
+        let mut data = Section::new("x86");
+        data.name = Some(String::from(".data"));
+        data.attrs.insert(String::from("flags"), String::from("aw"));
+        exe.add_image(&data, &MemoryImage::from_bytes(0x402000, b"DATA"));
+
+        let bytes = exe.to_bytes().unwrap();
+        let back = Elf::from_bytes(&bytes).unwrap();
+        let contents = |elf: &Elf| {
+            elf.sections
+                .iter()
+                .map(|s| s.data.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contents(&back)[2..], contents(&exe)[2..]);
+        assert_eq!(back.to_bytes().unwrap(), bytes);
+        for p in &back.segments {
+            assert_eq!(p.offset % PAGE, p.vaddr % PAGE);
+        }
+        let loaded = back.segments[1].offset as usize;
+        assert_eq!(&bytes[loaded..loaded + 4], b"DATA");
+        let symbols = back.symbol_table().unwrap();
+        assert_eq!(symbols.get("a_rather_long_label_7"), Some(label(0x401000)));
+        assert_eq!(symbols.get("K").unwrap().kind, SymbolKind::Constant);
+        let sections = back.image_sections("x86");
+        assert_eq!(sections[0].0.entry, Some(0x401000));
+        assert_eq!(sections[1].0.attr("flags"), Some("aw"));
+        assert_eq!(sections[1].1.get(0x402003), Some(b'A'));
+    }
+    #[test]
+    fn moved_contents_keep_their_segment() {
+        let mut exe = Elf::new(ElfClass::Elf32, Endian::Little, ET_EXEC, EM_MOS);
+        exe.add_image(
+            &Section::new("6502"),
+            &MemoryImage::from_bytes(0x0801, &[0xea]),
+        );
+        // names placed first over the contents, so that those have to move
+        exe.sections[1].offset = exe.sections[2].offset - 1;
+        let bytes = exe.to_bytes().unwrap();
+        let back = Elf::from_bytes(&bytes).unwrap();
+        let p = back.segments[0];
+        assert_eq!(p.offset, back.sections[2].offset);
+        assert_eq!(p.offset % PAGE, 0x801);
+        assert_eq!(bytes[p.offset as usize], 0xea);
+    }
+    #[test]
+    fn oversized_fields() {
+        let mut exe = Elf::new(ElfClass::Elf32, Endian::Little, ET_EXEC, EM_MOS);
+        exe.add_image(
+            &Section::new("6502"),
+            &MemoryImage::from_bytes(0x0801, &[0xea]),
+        );
+        exe.sections[1].offset = exe.sections[2].offset - 1;
+        exe.segments[0].filesz = u64::MAX;
+        assert_eq!(exe.to_bytes(), Err(ErrorKind::Corrupt));
+        exe.sections[2].offset = u64::MAX;
+        assert_eq!(exe.to_bytes(), Err(ErrorKind::Corrupt));
+
+        let mut exe = Elf::new(ElfClass::Elf64, Endian::Little, ET_EXEC, EM_X86_64);
+        exe.add_image(&Section::new("x86"), &MemoryImage::from_bytes(0, &[0x90]));
+        exe.sections[2].addr = u64::MAX;
+        let mut table = SymbolTable::new();
+        table.define("top", label(u64::MAX));
+        let mut with = exe.clone();
+        let symtab = with.add_symbol_table(&table).unwrap();
+        assert_eq!(with.symbols(symtab).unwrap()[1].shndx, 2);
+        // the image at the first reserved index
+        let null = exe.sections[0].clone();
+        exe.sections
+            .splice(2..2, core::iter::repeat_n(null, SHN_LORESERVE as usize - 2));
+        assert_eq!(exe.add_symbol_table(&table), Err(ErrorKind::Format));
+    }
+    #[test]
+    fn relocatable_round_trip() {
+        let mut obj = Elf::new(ElfClass::Elf32, Endian::Little, ET_REL, EM_MOS);
+        let mut text = Section::new("6502");
+        text.org = Some(0x0801);
+        let index = obj.add_image(&text, &MemoryImage::from_bytes(0x0801, &[0xa9, 1, 0x60]));
+        assert_eq!(obj.sections[index].addr, 0);
+        let mut table = SymbolTable::new();
+        table.define("start", label(0x0801));
+        assert_eq!(obj.add_symbol_table(&table), Err(ErrorKind::Unsupported));
+        obj.add_symbols(&[ElfSymbol {
+            name: String::from("done"),
+            value: 2,
+            size: 1,
+            binding: STB_GLOBAL,
+            kind: STT_FUNC,
+            other: 0,
+            shndx: index as u16,
+        }])
+        .unwrap();
+
+        let bytes = obj.to_bytes().unwrap();
+        let back = Elf::from_bytes(&bytes).unwrap();
+        assert_eq!(back.sections[index].data, obj.sections[index].data);
+        assert_eq!(back.to_bytes().unwrap(), bytes);
+        assert_eq!(back.symbol_table().unwrap().get("done"), Some(label(2)));
+        let sections = back.image_sections("6502");
+        assert_eq!((sections[0].0.org, sections[0].0.entry), (None, None));
+        assert_eq!(sections[0].1.get(2), Some(0x60));
+    }
+    #[test]
+    fn malformed_file() {
+        assert_eq!(Elf::from_bytes(b"\x7fELG"), Err(ErrorKind::BadMagic));
+        assert_eq!(
+            Elf::from_bytes(b"\x7fELF\x01\x01"),
+            Err(ErrorKind::Truncated)
+        );
+        let mut bytes = Elf::new(ElfClass::Elf32, Endian::Big, ET_REL, EM_68K)
+            .to_bytes()
+            .unwrap();
+        assert_eq!(Elf::from_bytes(&bytes[..40]), Err(ErrorKind::Truncated));
+        bytes[4] = 3;
+        assert_eq!(Elf::from_bytes(&bytes), Err(ErrorKind::Unsupported));
+    }
+}
@@ -0,0 +1,396 @@
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, TryAsRef};
+use alloc::{string::String, vec::Vec};
+
+/// The bitmap the boot ROM compares against $0104–$0133 before starting a
+/// cartridge.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+const LOGO_AT: usize = 0x104;
+const HEADER_END: usize = 0x150;
+const HEADER_CHECKSUM_AT: usize = 0x14d;
+const GLOBAL_CHECKSUM_AT: usize = 0x14e;
+
+/// Memory bank controllers named by the cartridge type byte at $0147.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Mbc {
+    /// 32 KiB mapped flat, no controller.
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+impl Mbc {
+    /// Size of the switchable ROM banks.
+    pub fn bank_len(self) -> usize {
+        match self {
+            Mbc::RomOnly => 0x8000,
+            Mbc::Mbc6 => 0x2000,
+            _ => 0x4000,
+        }
+    }
+}
+
+/// Colour support announced at $0143.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum CgbSupport {
+    None,
+    /// Runs on both DMG and CGB ($80).
+    Compatible,
+    /// Requires a CGB ($C0).
+    Only,
+}
+
+/// The cartridge header at $0100–$014F, field by field.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GbHeader {
+    /// Code at $0100, usually `NOP; JP start`.
+    pub entry: [u8; 4],
+    /// Title in upper-case ASCII, padded with zeros. Later cartridges use
+    /// the last four bytes for a manufacturer code.
+    pub title: [u8; 15],
+    pub cgb_flag: u8,
+    pub new_licensee: [u8; 2],
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub destination: u8,
+    pub old_licensee: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    /// Big-endian, unlike everything else on the system.
+    pub global_checksum: u16,
+}
+
+impl GbHeader {
+    /// The title up to its zero padding.
+    pub fn title(&self) -> &[u8] {
+        let end = self.title.iter().position(|&b| b == 0).unwrap_or(15);
+        &self.title[..end]
+    }
+
+    pub fn cgb(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0x80 => CgbSupport::Compatible,
+            0xc0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        }
+    }
+
+    /// Super Game Boy functions are only enabled together with the old
+    /// licensee code $33.
+    pub fn sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    pub fn mbc(&self) -> Option<Mbc> {
+        Some(match self.cartridge_type {
+            0x00 | 0x08 | 0x09 => Mbc::RomOnly,
+            0x01..=0x03 => Mbc::Mbc1,
+            0x05 | 0x06 => Mbc::Mbc2,
+            0x0b..=0x0d => Mbc::Mmm01,
+            0x0f..=0x13 => Mbc::Mbc3,
+            0x19..=0x1e => Mbc::Mbc5,
+            0x20 => Mbc::Mbc6,
+            0x22 => Mbc::Mbc7,
+            0xfc => Mbc::PocketCamera,
+            0xfd => Mbc::Tama5,
+            0xfe => Mbc::HuC3,
+            0xff => Mbc::HuC1,
+            _ => return None,
+        })
+    }
+
+    /// Whether the cartridge keeps its RAM (or clock) powered.
+    pub fn battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xfc..=0xff
+        )
+    }
+
+    pub fn rom_len(&self) -> Option<usize> {
+        (self.rom_size <= 8).then(|| 0x8000 << self.rom_size)
+    }
+
+    /// External RAM size. MBC2's built-in 512 nibbles are not counted.
+    pub fn ram_len(&self) -> Option<usize> {
+        Some(match self.ram_size {
+            0 => 0,
+            1 => 0x800,
+            2 => 0x2000,
+            3 => 0x8000,
+            4 => 0x20000,
+            5 => 0x10000,
+            _ => return None,
+        })
+    }
+}
+
+/// The header checksum over $0134–$014C, as the boot ROM computes it.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..HEADER_CHECKSUM_AT]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// The sum of every byte but the global checksum itself.
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != GLOBAL_CHECKSUM_AT && i != GLOBAL_CHECKSUM_AT + 1)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+/// A Game Boy / Game Boy Color cartridge image.
+///
+/// `data` is the whole ROM including the header area, and must stay at
+/// least $150 bytes long; `header` is decoded from it and written back over
+/// it by [`GbRom::repair`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GbRom {
+    pub header: GbHeader,
+    pub data: Vec<u8>,
+}
+
+impl GbRom {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        let h = bytes.get(..HEADER_END).ok_or(ErrorKind::Truncated)?;
+        let header = GbHeader {
+            entry: h[0x100..0x104].try_into().unwrap(),
+            title: h[0x134..0x143].try_into().unwrap(),
+            cgb_flag: h[0x143],
+            new_licensee: [h[0x144], h[0x145]],
+            sgb_flag: h[0x146],
+            cartridge_type: h[0x147],
+            rom_size: h[0x148],
+            ram_size: h[0x149],
+            destination: h[0x14a],
+            old_licensee: h[0x14b],
+            version: h[0x14c],
+            header_checksum: h[HEADER_CHECKSUM_AT],
+            global_checksum: u16::from_be_bytes([h[0x14e], h[0x14f]]),
+        };
+        Ok(Self {
+            header,
+            data: bytes.to_vec(),
+        })
+    }
+
+    pub fn logo_ok(&self) -> bool {
+        self.data[LOGO_AT..LOGO_AT + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+    }
+
+    pub fn header_checksum_ok(&self) -> bool {
+        header_checksum(&self.data) == self.data[HEADER_CHECKSUM_AT]
+    }
+
+    pub fn global_checksum_ok(&self) -> bool {
+        let stored = [
+            self.data[GLOBAL_CHECKSUM_AT],
+            self.data[GLOBAL_CHECKSUM_AT + 1],
+        ];
+        global_checksum(&self.data) == u16::from_be_bytes(stored)
+    }
+
+    /// Write the header fields back into the ROM, restore the logo, and
+    /// recompute both checksums, updating them in `header` too.
+    pub fn repair(&mut self) {
+        let h = &self.header;
+        let d = &mut self.data;
+        d[0x100..0x104].copy_from_slice(&h.entry);
+        d[LOGO_AT..LOGO_AT + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        d[0x134..0x143].copy_from_slice(&h.title);
+        d[0x143] = h.cgb_flag;
+        d[0x144..0x146].copy_from_slice(&h.new_licensee);
+        d[0x146] = h.sgb_flag;
+        d[0x147] = h.cartridge_type;
+        d[0x148] = h.rom_size;
+        d[0x149] = h.ram_size;
+        d[0x14a] = h.destination;
+        d[0x14b] = h.old_licensee;
+        d[0x14c] = h.version;
+        self.header.header_checksum = header_checksum(d);
+        d[HEADER_CHECKSUM_AT] = self.header.header_checksum;
+        self.header.global_checksum = global_checksum(d);
+        d[GLOBAL_CHECKSUM_AT..HEADER_END]
+            .copy_from_slice(&self.header.global_checksum.to_be_bytes());
+    }
+
+    /// The repaired ROM.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rom = self.clone();
+        rom.repair();
+        rom.data
+    }
+
+    /// The fixed area at $0000 followed by each switchable bank, with its
+    /// bank number counted in the controller's bank size. Unknown
+    /// cartridge types are [`ErrorKind::Unsupported`], and a cartridge
+    /// without a controller that is larger than the 32 KiB it can map is
+    /// [`ErrorKind::Corrupt`].
+    pub fn banks(&self) -> Result<Vec<(u64, &[u8])>, ErrorKind> {
+        let mbc = self.header.mbc().ok_or(ErrorKind::Unsupported)?;
+        let bank_len = mbc.bank_len();
+        if mbc == Mbc::RomOnly && self.data.len() > bank_len {
+            return Err(ErrorKind::Corrupt);
+        }
+        let fixed = self.data.len().min(0x4000.max(bank_len));
+        let (head, tail) = self.data.split_at(fixed);
+        let first = (fixed / bank_len) as u64;
+        Ok(core::iter::once((0, head))
+            .chain(
+                tail.chunks(bank_len)
+                    .enumerate()
+                    .map(|(i, bank)| (first + i as u64, bank)),
+            )
+            .collect())
+    }
+
+    /// Each bank as a section: the fixed area at $0000 and the switchable
+    /// banks at $4000. MBC6 banks can appear in either of its two 8 KiB
+    /// windows and get no origin; a cartridge without a controller is one
+    /// unbanked section.
+    pub fn sections(&self, arch: &str) -> Result<Vec<(Section, MemoryImage)>, ErrorKind> {
+        let mbc = self.header.mbc().ok_or(ErrorKind::Unsupported)?;
+        Ok(self
+            .banks()?
+            .into_iter()
+            .map(|(bank, data)| {
+                let mut section = Section::new(arch);
+                section.org = match (bank, mbc) {
+                    (0, _) => Some(0),
+                    (_, Mbc::Mbc6) => None,
+                    _ => Some(0x4000),
+                };
+                if mbc != Mbc::RomOnly {
+                    section.bank = Some(bank);
+                }
+                let image = MemoryImage::from_bytes(section.org.unwrap_or(0), data);
+                (section, image)
+            })
+            .collect())
+    }
+}
+
+/// The Game Boy cartridge format, taking the whole remaining byte stream as
+/// one ROM. Writing repairs the logo and checksums.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GbParser;
+
+impl<T: From<GbRom>, Err: From<ErrorKind>> FileParser<T, Err> for GbParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let rom = GbRom::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(rom)))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        let rom = GbRom::from_bytes(bytes).ok()?;
+        let mut confidence = if rom.logo_ok() { 80 } else { 0 };
+        if rom.header_checksum_ok() {
+            confidence += 20;
+        }
+        if matches!(extension, Some("gb" | "gbc" | "sgb")) {
+            confidence += 10;
+        }
+        (confidence > 10).then_some(confidence)
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("Game Boy cartridge"))
+    }
+}
+
+impl<T: TryAsRef<GbRom>, Err: From<ErrorKind>> FileWriter<T, Err> for GbParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let rom = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend(rom.to_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn mbc1_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x143] = 0x80;
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        rom
+    }
+
+    #[test]
+    fn round_trip_repairs_header() {
+        let mut rom = GbRom::from_bytes(&mbc1_rom()).unwrap();
+        assert_eq!(rom.header.title(), b"TEST");
+        assert_eq!(rom.header.cgb(), CgbSupport::Compatible);
+        assert_eq!(rom.header.mbc(), Some(Mbc::Mbc1));
+        assert_eq!(rom.header.rom_len(), Some(0x10000));
+        assert!(!rom.logo_ok() && !rom.header_checksum_ok());
+        let back = GbRom::from_bytes(&rom.to_bytes()).unwrap();
+        assert!(back.logo_ok() && back.header_checksum_ok() && back.global_checksum_ok());
+        rom.repair();
+        assert_eq!(rom, back);
+        assert_eq!(back.to_bytes(), back.data);
+    }
+    #[test]
+    fn banked_sections() {
+        let rom = GbRom::from_bytes(&mbc1_rom()).unwrap();
+        let sections = rom.sections("sm83").unwrap();
+        assert_eq!(sections.len(), 4);
+        assert_eq!((sections[0].0.org, sections[0].0.bank), (Some(0), Some(0)));
+        assert_eq!(
+            (sections[2].0.org, sections[2].0.bank),
+            (Some(0x4000), Some(2))
+        );
+        let mut plain = rom.clone();
+        plain.header.cartridge_type = 0;
+        // 64 KiB cannot be mapped without a controller
+        assert_eq!(plain.sections("sm83").unwrap_err(), ErrorKind::Corrupt);
+        plain.data.truncate(0x8000);
+        let sections = plain.sections("sm83").unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!((sections[0].0.org, sections[0].0.bank), (Some(0), None));
+        assert_eq!(sections[0].1.end(), Some(0x8000));
+    }
+    #[test]
+    fn malformed_rom() {
+        assert_eq!(
+            GbRom::from_bytes(&[0; HEADER_END - 1]).unwrap_err(),
+            ErrorKind::Truncated
+        );
+        let mut rom = GbRom::from_bytes(&mbc1_rom()).unwrap();
+        rom.header.cartridge_type = 0xee;
+        assert_eq!(rom.sections("sm83").unwrap_err(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,283 @@
+use super::{decode_hex, next_line, write_hex};
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, Locate, TryAsRef};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use nom::error::ErrorKind as NomKind;
+
+/// Start address from a type 03 or 05 record.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum IhexStart {
+    /// CS:IP, for real-mode x86.
+    Segment { cs: u16, ip: u16 },
+    /// EIP.
+    Linear(u32),
+}
+
+impl IhexStart {
+    pub fn address(self) -> u64 {
+        match self {
+            IhexStart::Segment { cs, ip } => ((cs as u64) << 4) + ip as u64,
+            IhexStart::Linear(eip) => eip as u64,
+        }
+    }
+}
+
+/// The contents of an Intel HEX file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntelHex {
+    pub image: MemoryImage,
+    pub start: Option<IhexStart>,
+}
+
+impl From<IntelHex> for MemoryImage {
+    fn from(hex: IntelHex) -> Self {
+        hex.image
+    }
+}
+
+/// Parse Intel HEX records up to and including the end-of-file record,
+/// returning the text after it.
+///
+/// Data records are placed relative to the last extended segment (02) or
+/// extended linear (04) address. After a segment address they wrap within
+/// their 64 KiB window; after a linear one they run on past it.
+/// Errors are raised at the offending record.
+pub fn parse_ihex<Err: From<ErrorKind> + Locate>(text: &str) -> Result<(&str, IntelHex), Err> {
+    let mut out = IntelHex::default();
+    let mut base: u64 = 0;
+    let mut segmented = false;
+    let mut rest = text;
+    loop {
+        let at = rest;
+        let fail = |kind| Err::from(kind).at_meta(at);
+        let Some((line, next)) = next_line(rest) else {
+            return Err(fail(ErrorKind::Truncated));
+        };
+        rest = next;
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .ok_or_else(|| fail(ErrorKind::Nom(NomKind::Char)))?;
+        let bytes = decode_hex(record).ok_or_else(|| fail(ErrorKind::Nom(NomKind::HexDigit)))?;
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+            return Err(fail(ErrorKind::Nom(NomKind::LengthValue)));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(fail(ErrorKind::Checksum));
+        }
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data) {
+            (0x00, _) if !segmented => {
+                out.image.insert(base + offset as u64, data).map_err(fail)?;
+            }
+            (0x00, _) => {
+                // data wraps around within the current 64 KiB segment
+                let first = data.len().min(0x10000 - offset as usize);
+                out.image
+                    .insert(base + offset as u64, &data[..first])
+                    .map_err(fail)?;
+                out.image.insert(base, &data[first..]).map_err(fail)?;
+            }
+            (0x01, _) => return Ok((rest, out)),
+            (0x02, &[hi, lo]) => {
+                base = (u16::from_be_bytes([hi, lo]) as u64) << 4;
+                segmented = true;
+            }
+            (0x03, &[a, b, c, d]) => {
+                out.start = Some(IhexStart::Segment {
+                    cs: u16::from_be_bytes([a, b]),
+                    ip: u16::from_be_bytes([c, d]),
+                })
+            }
+            (0x04, &[hi, lo]) => {
+                base = (u16::from_be_bytes([hi, lo]) as u64) << 16;
+                segmented = false;
+            }
+            (0x05, &[a, b, c, d]) => {
+                out.start = Some(IhexStart::Linear(u32::from_be_bytes([a, b, c, d])))
+            }
+            _ => return Err(fail(ErrorKind::Corrupt)),
+        }
+    }
+}
+
+fn write_record<W: Write>(w: &mut W, kind: u8, offset: u16, data: &[u8]) -> core::fmt::Result {
+    let [hi, lo] = offset.to_be_bytes();
+    let head = [data.len() as u8, hi, lo, kind];
+    let sum = head
+        .iter()
+        .chain(data)
+        .fold(0u8, |sum, &b| sum.wrapping_add(b));
+    w.write_char(':')?;
+    write_hex(w, &head)?;
+    write_hex(w, data)?;
+    write_hex(w, &[sum.wrapping_neg()])?;
+    w.write_char('\n')
+}
+
+/// Write `image` as Intel HEX with up to `record_len` data bytes per record,
+/// using extended linear address records above 64 KiB, then the start
+/// address if given and the end-of-file record.
+///
+/// Images reaching past 4 GiB and record lengths outside 1–255 are
+/// [`ErrorKind::Format`] errors.
+pub fn write_ihex<W: Write>(
+    w: &mut W,
+    image: &MemoryImage,
+    start: Option<IhexStart>,
+    record_len: usize,
+) -> Result<(), ErrorKind> {
+    if !(1..=255).contains(&record_len) || image.end().is_some_and(|end| end > 1 << 32) {
+        return Err(ErrorKind::Format);
+    }
+    let fmt = |_| ErrorKind::Format;
+    let mut upper = 0;
+    for (start, data) in image.segments() {
+        let mut addr = start;
+        let mut data = data;
+        while !data.is_empty() {
+            if addr >> 16 != upper {
+                upper = addr >> 16;
+                write_record(w, 0x04, 0, &(upper as u16).to_be_bytes()).map_err(fmt)?;
+            }
+            let room = 0x10000 - (addr & 0xffff) as usize;
+            let (chunk, rest) = data.split_at(data.len().min(record_len).min(room));
+            write_record(w, 0x00, addr as u16, chunk).map_err(fmt)?;
+            addr += chunk.len() as u64;
+            data = rest;
+        }
+    }
+    match start {
+        Some(IhexStart::Segment { cs, ip }) => {
+            let [a, b] = cs.to_be_bytes();
+            let [c, d] = ip.to_be_bytes();
+            write_record(w, 0x03, 0, &[a, b, c, d]).map_err(fmt)?;
+        }
+        Some(IhexStart::Linear(eip)) => {
+            write_record(w, 0x05, 0, &eip.to_be_bytes()).map_err(fmt)?;
+        }
+        None => {}
+    }
+    write_record(w, 0x01, 0, &[]).map_err(fmt)
+}
+
+/// The Intel HEX format, read from a section's metadata text.
+///
+/// Works with any registry whose values can be made from an [`IntelHex`],
+/// and writes those that hold one.
+#[derive(Clone, Copy, Debug)]
+pub struct IhexParser {
+    /// Data bytes per record when writing.
+    pub record_len: usize,
+}
+
+impl Default for IhexParser {
+    fn default() -> Self {
+        Self { record_len: 16 }
+    }
+}
+
+impl<T: From<IntelHex>, Err: From<ErrorKind> + Locate> FileParser<T, Err> for IhexParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let (meta, hex) = parse_ihex(meta).map_err(nom::Err::Error)?;
+        Ok((bytes, meta, T::from(hex)))
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("Intel HEX"))
+    }
+}
+
+impl<T: TryAsRef<IntelHex>, Err: From<ErrorKind>> FileWriter<T, Err> for IhexParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        _bytes: &mut Vec<u8>,
+        meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let hex = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        write_ihex(meta, &hex.image, hex.start, self.record_len)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+
+    #[test]
+    fn round_trip() {
+        let text = ":0300300002337A1E\n:020000040001F9\n:02FFFF00AABB9B\n:04000005000000CD2A\n:00000001FF\nrest";
+        let (rest, hex) = parse_ihex::<ErrorKind>(text).unwrap();
+        assert_eq!(rest, "rest");
+        assert_eq!(hex.image.get(0x30), Some(0x02));
+        assert_eq!(hex.start, Some(IhexStart::Linear(0xcd)));
+        let mut out = String::new();
+        write_ihex(&mut out, &hex.image, hex.start, 16).unwrap();
+        assert_eq!(parse_ihex::<ErrorKind>(&out).unwrap(), ("", hex));
+    }
+    #[test]
+    fn window_crossing() {
+        // linear: the second byte runs on to $20000
+        let linear = ":020000040001F9\n:02FFFF00AABB9B\n:00000001FF\n";
+        let (_, hex) = parse_ihex::<ErrorKind>(linear).unwrap();
+        assert_eq!(hex.image.get(0x1ffff), Some(0xaa));
+        assert_eq!(hex.image.get(0x20000), Some(0xbb));
+        // segment: it wraps to the start of the segment at $10000
+        let segment = ":020000021000EC\n:02FFFF00AABB9B\n:00000001FF\n";
+        let (_, hex) = parse_ihex::<ErrorKind>(segment).unwrap();
+        assert_eq!(hex.image.get(0x1ffff), Some(0xaa));
+        assert_eq!(hex.image.get(0x10000), Some(0xbb));
+    }
+    #[test]
+    fn registry_round_trip() {
+        let parser = IhexParser::default();
+        let mut registry: FileRegistry<IntelHex, ErrorKind> = FileRegistry::default();
+        registry.register_format("ihex".into(), &parser);
+        let hex = IntelHex {
+            image: MemoryImage::from_bytes(0x18000, &[1, 2, 3]),
+            start: Some(IhexStart::Segment { cs: 0x1800, ip: 0 }),
+        };
+        let sections = [(Section::new("ihex"), hex)];
+        let (mut bytes, mut meta) = (Vec::new(), String::new());
+        registry
+            .write_file(&sections, &mut bytes, &mut meta)
+            .unwrap();
+        let (_, rest, back) = registry.parse_sections(&bytes, &meta).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(back, sections);
+    }
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            parse_ihex::<ErrorKind>(":0300300002337A1F\n").unwrap_err(),
+            ErrorKind::Checksum
+        );
+        assert_eq!(
+            parse_ihex::<ErrorKind>(":0300300002337A1E\n").unwrap_err(),
+            ErrorKind::Truncated
+        );
+        assert_eq!(
+            parse_ihex::<ErrorKind>(":00000006FA\n").unwrap_err(),
+            ErrorKind::Corrupt
+        );
+        let text = ":020000040001F9\n:0300300002337A1F\n";
+        let e = parse_ihex::<Diagnostic>(text)
+            .unwrap_err()
+            .locate(text, &[], "ihex");
+        assert_eq!(e.location.unwrap().line, 2);
+    }
+}
@@ -0,0 +1,35 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+pub mod atari;
+pub mod cbm;
+pub mod elf;
+pub mod gb;
+pub mod ihex;
+pub mod nes;
+pub mod snes;
+pub mod srec;
+
+/// Decode a run of hex digit pairs.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn write_hex(w: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|b| write!(w, "{:02X}", b))
+}
+
+/// Split the next line off `text`, without its `\n` or `\r\n`.
+fn next_line(text: &str) -> Option<(&str, &str)> {
+    if text.is_empty() {
+        return None;
+    }
+    let (line, rest) = text.split_once('\n').unwrap_or((text, ""));
+    Some((line.strip_suffix('\r').unwrap_or(line), rest))
+}
@@ -0,0 +1,478 @@
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, TryAsRef};
+use alloc::{string::String, vec::Vec};
+
+const MAGIC: &[u8; 4] = b"NES\x1a";
+const HEADER_LEN: usize = 16;
+const TRAINER_LEN: usize = 512;
+
+/// Nametable arrangement wired on the cartridge (flags 6 bit 0).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+}
+
+/// Console the cartridge is for (flags 7 bits 0–1).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// NES 2.0 only: see [`Nes2Fields::system`].
+    Extended,
+}
+
+/// The header fields NES 2.0 adds over iNES.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub struct Nes2Fields {
+    pub submapper: u8,
+    /// PRG-RAM is `64 << shift` bytes, or absent if the shift is 0.
+    pub prg_ram_shift: u8,
+    pub prg_nvram_shift: u8,
+    pub chr_ram_shift: u8,
+    pub chr_nvram_shift: u8,
+    /// CPU/PPU timing (byte 12).
+    pub timing: u8,
+    /// Vs. System or extended console type (byte 13).
+    pub system: u8,
+    /// Number of miscellaneous ROMs after CHR-ROM (byte 14).
+    pub misc_roms: u8,
+    /// Default expansion device (byte 15).
+    pub expansion: u8,
+    /// PRG-ROM size as found: byte 4 and the low nibble of byte 9. Written
+    /// back as long as it still gives the PRG-ROM length, since a size can
+    /// be expressed in more than one way.
+    pub prg_size: (u8, u8),
+    /// CHR-ROM size as found: byte 5 and the high nibble of byte 9.
+    pub chr_size: (u8, u8),
+}
+
+/// Header fields that depend on the header version.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum NesVersion {
+    /// iNES, with the version bits of flags 7 (normally 0) and bytes 8–15
+    /// kept as found, since dumps often carry junk there.
+    INes {
+        id: u8,
+        tail: [u8; 8],
+    },
+    Nes2(Nes2Fields),
+}
+
+/// A decoded iNES or NES 2.0 header. ROM sizes are not stored here; they
+/// follow from the lengths of the ROM data when the file is written.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct NesHeader {
+    /// Mapper number: 8 bits for iNES, 12 for NES 2.0.
+    pub mapper: u16,
+    pub mirroring: Mirroring,
+    pub four_screen: bool,
+    /// Battery-backed or other non-volatile memory.
+    pub battery: bool,
+    pub console: ConsoleType,
+    pub version: NesVersion,
+}
+
+impl NesHeader {
+    /// The NES 2.0 submapper, or 0 for iNES.
+    pub fn submapper(&self) -> u8 {
+        match self.version {
+            NesVersion::INes { .. } => 0,
+            NesVersion::Nes2(f) => f.submapper,
+        }
+    }
+}
+
+/// An iNES or NES 2.0 cartridge image.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NesRom {
+    pub header: NesHeader,
+    /// 512-byte trainer loaded at $7000.
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    /// Anything after CHR-ROM, such as NES 2.0 miscellaneous ROMs.
+    pub misc: Vec<u8>,
+}
+
+impl NesRom {
+    pub const PRG_BANK: usize = 0x4000;
+    pub const CHR_BANK: usize = 0x2000;
+
+    /// PRG-ROM in 16 KiB banks; a short final bank is possible with NES 2.0
+    /// exponent sizes.
+    pub fn prg_banks(&self) -> impl Iterator<Item = &[u8]> {
+        self.prg_rom.chunks(Self::PRG_BANK)
+    }
+
+    /// CHR-ROM in 8 KiB banks.
+    pub fn chr_banks(&self) -> impl Iterator<Item = &[u8]> {
+        self.chr_rom.chunks(Self::CHR_BANK)
+    }
+
+    /// PRG-ROM banks as sections. NROM (mapper 0) banks originate at
+    /// $8000 and $C000, a single bank at $C000; banks of other mappers can
+    /// be switched into different windows and only get their bank number.
+    pub fn sections(&self, arch: &str) -> Vec<(Section, MemoryImage)> {
+        let banks = self.prg_rom.len().div_ceil(Self::PRG_BANK);
+        self.prg_banks()
+            .enumerate()
+            .map(|(i, data)| {
+                let mut section = Section::new(arch);
+                if self.header.mapper == 0 {
+                    section.org = Some(if i + 1 == banks { 0xc000 } else { 0x8000 });
+                } else {
+                    section.bank = Some(i as u64);
+                }
+                let image = MemoryImage::from_bytes(section.org.unwrap_or(0), data);
+                (section, image)
+            })
+            .collect()
+    }
+
+    /// Parse a whole `.nes` file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(ErrorKind::BadMagic);
+        }
+        let h: &[u8; HEADER_LEN] = bytes
+            .get(..HEADER_LEN)
+            .and_then(|h| h.try_into().ok())
+            .ok_or(ErrorKind::Truncated)?;
+        let nes2 = h[7] & 0x0c == 0x08;
+        let (prg_len, chr_len) = if nes2 {
+            (
+                decode_size(h[4], h[9] & 0x0f, Self::PRG_BANK),
+                decode_size(h[5], h[9] >> 4, Self::CHR_BANK),
+            )
+        } else {
+            (
+                h[4] as usize * Self::PRG_BANK,
+                h[5] as usize * Self::CHR_BANK,
+            )
+        };
+
+        let mut mapper = (h[6] >> 4) as u16 | (h[7] & 0xf0) as u16;
+        let version = if nes2 {
+            mapper |= ((h[8] & 0x0f) as u16) << 8;
+            NesVersion::Nes2(Nes2Fields {
+                submapper: h[8] >> 4,
+                prg_ram_shift: h[10] & 0x0f,
+                prg_nvram_shift: h[10] >> 4,
+                chr_ram_shift: h[11] & 0x0f,
+                chr_nvram_shift: h[11] >> 4,
+                timing: h[12],
+                system: h[13],
+                misc_roms: h[14],
+                expansion: h[15],
+                prg_size: (h[4], h[9] & 0x0f),
+                chr_size: (h[5], h[9] >> 4),
+            })
+        } else {
+            NesVersion::INes {
+                id: (h[7] >> 2) & 0x03,
+                tail: h[8..].try_into().unwrap(),
+            }
+        };
+        let console = match h[7] & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended,
+        };
+        let header = NesHeader {
+            mapper,
+            mirroring: if h[6] & 0x01 != 0 {
+                Mirroring::Vertical
+            } else {
+                Mirroring::Horizontal
+            },
+            four_screen: h[6] & 0x08 != 0,
+            battery: h[6] & 0x02 != 0,
+            console,
+            version,
+        };
+
+        let mut rest = &bytes[HEADER_LEN..];
+        let mut take = |n: usize| {
+            let (a, b) = rest.split_at_checked(n).ok_or(ErrorKind::Truncated)?;
+            rest = b;
+            Ok::<_, ErrorKind>(a.to_vec())
+        };
+        let trainer = if h[6] & 0x04 != 0 {
+            Some(take(TRAINER_LEN)?)
+        } else {
+            None
+        };
+        let prg_rom = take(prg_len)?;
+        let chr_rom = take(chr_len)?;
+        Ok(Self {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+            misc: rest.to_vec(),
+        })
+    }
+
+    /// Write the whole `.nes` file.
+    ///
+    /// ROM sizes are written in 16 KiB/8 KiB units where they fit, and in
+    /// NES 2.0 exponent-multiplier form otherwise; sizes an iNES header
+    /// cannot hold, a trainer that is not 512 bytes, or an iNES mapper above
+    /// 255 are [`ErrorKind::Format`] errors.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ErrorKind> {
+        let h = &self.header;
+        let mut out = Vec::with_capacity(
+            HEADER_LEN + TRAINER_LEN + self.prg_rom.len() + self.chr_rom.len() + self.misc.len(),
+        );
+        out.extend_from_slice(MAGIC);
+
+        let (prg_raw, chr_raw) = match h.version {
+            NesVersion::INes { .. } => (None, None),
+            NesVersion::Nes2(f) => (Some(f.prg_size), Some(f.chr_size)),
+        };
+        let (prg_lsb, prg_msb) = encode_size(self.prg_rom.len(), Self::PRG_BANK, prg_raw)?;
+        let (chr_lsb, chr_msb) = encode_size(self.chr_rom.len(), Self::CHR_BANK, chr_raw)?;
+        out.extend([prg_lsb, chr_lsb]);
+
+        if self
+            .trainer
+            .as_ref()
+            .is_some_and(|t| t.len() != TRAINER_LEN)
+        {
+            return Err(ErrorKind::Format);
+        }
+        let flags6 = ((h.mapper & 0x0f) as u8) << 4
+            | (h.four_screen as u8) << 3
+            | (self.trainer.is_some() as u8) << 2
+            | (h.battery as u8) << 1
+            | (h.mirroring == Mirroring::Vertical) as u8;
+        let console = match h.console {
+            ConsoleType::Nes => 0,
+            ConsoleType::VsSystem => 1,
+            ConsoleType::Playchoice10 => 2,
+            ConsoleType::Extended => 3,
+        };
+        let id = match h.version {
+            NesVersion::INes { id, .. } => id & 0x03,
+            NesVersion::Nes2(_) => 0x02,
+        };
+        let flags7 = (h.mapper & 0xf0) as u8 | id << 2 | console;
+        out.extend([flags6, flags7]);
+
+        match h.version {
+            NesVersion::INes { tail, .. } => {
+                if h.mapper > 0xff {
+                    return Err(ErrorKind::Format);
+                }
+                out.extend(tail);
+            }
+            NesVersion::Nes2(f) => out.extend([
+                (f.submapper << 4) | ((h.mapper >> 8) & 0x0f) as u8,
+                (chr_msb << 4) | prg_msb,
+                (f.prg_nvram_shift << 4) | (f.prg_ram_shift & 0x0f),
+                (f.chr_nvram_shift << 4) | (f.chr_ram_shift & 0x0f),
+                f.timing,
+                f.system,
+                f.misc_roms,
+                f.expansion,
+            ]),
+        }
+
+        if let Some(trainer) = &self.trainer {
+            out.extend_from_slice(trainer);
+        }
+        out.extend_from_slice(&self.prg_rom);
+        out.extend_from_slice(&self.chr_rom);
+        out.extend_from_slice(&self.misc);
+        Ok(out)
+    }
+}
+
+/// ROM size from its header LSB and MSB nibble.
+fn decode_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0f {
+        // exponent-multiplier form: 2^E * (MM*2+1)
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+/// Header LSB and MSB nibble for a ROM of `len` bytes. For NES 2.0, `raw`
+/// is the size the header was read with, kept if it still matches `len`.
+fn encode_size(len: usize, unit: usize, raw: Option<(u8, u8)>) -> Result<(u8, u8), ErrorKind> {
+    if let Some((lsb, msb)) = raw
+        && msb <= 0x0f
+        && decode_size(lsb, msb, unit) == len
+    {
+        return Ok((lsb, msb));
+    }
+    let nes2 = raw.is_some();
+    let max_units = if nes2 { 0xeff } else { 0xff };
+    if len.is_multiple_of(unit) && len / unit <= max_units {
+        let units = len / unit;
+        return Ok((units as u8, (units >> 8) as u8));
+    }
+    if nes2 {
+        for mm in 0..4u8 {
+            let multiplier = mm as usize * 2 + 1;
+            if len.is_multiple_of(multiplier) && (len / multiplier).is_power_of_two() {
+                let exponent = (len / multiplier).trailing_zeros() as u8;
+                if exponent < 64 {
+                    return Ok((exponent << 2 | mm, 0x0f));
+                }
+            }
+        }
+    }
+    Err(ErrorKind::Format)
+}
+
+/// The iNES / NES 2.0 cartridge format, taking the whole remaining byte
+/// stream as one `.nes` file.
+///
+/// Works with any registry whose values can be made from a [`NesRom`], and
+/// writes those that hold one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NesParser;
+
+impl<T: From<NesRom>, Err: From<ErrorKind>> FileParser<T, Err> for NesParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let rom = NesRom::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(rom)))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        if !bytes.starts_with(MAGIC) {
+            return None;
+        }
+        Some(if extension == Some("nes") { 110 } else { 100 })
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("iNES / NES 2.0 cartridge"))
+    }
+}
+
+impl<T: TryAsRef<NesRom>, Err: From<ErrorKind>> FileWriter<T, Err> for NesParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let rom = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        bytes.extend(rom.to_bytes()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn ines(flags6: u8, prg: u8, chr: u8, tail: [u8; 8]) -> Vec<u8> {
+        let mut f = MAGIC.to_vec();
+        f.extend([prg, chr, flags6, 0]);
+        f.extend(tail);
+        if flags6 & 0x04 != 0 {
+            f.extend((0..TRAINER_LEN).map(|i| i as u8));
+        }
+        f.extend((0..prg as usize * NesRom::PRG_BANK).map(|i| (i >> 8) as u8));
+        f.extend((0..chr as usize * NesRom::CHR_BANK).map(|i| (i * 3) as u8));
+        f
+    }
+
+    #[test]
+    fn ines_round_trip() {
+        let f = ines(0x13, 2, 1, [0; 8]);
+        let rom = NesRom::from_bytes(&f).unwrap();
+        assert_eq!(rom.header.mapper, 1);
+        assert_eq!(rom.header.mirroring, Mirroring::Vertical);
+        assert!(rom.header.battery);
+        assert_eq!(rom.prg_banks().count(), 2);
+        assert_eq!(rom.chr_banks().count(), 1);
+        assert_eq!(rom.to_bytes().unwrap(), f);
+        // a trainer and junk in the header tail survive
+        let f = ines(0x04, 1, 0, *b"DiskDude");
+        let rom = NesRom::from_bytes(&f).unwrap();
+        assert!(rom.trainer.is_some());
+        assert_eq!(rom.to_bytes().unwrap(), f);
+    }
+    #[test]
+    fn nes2_round_trip() {
+        let mut f = MAGIC.to_vec();
+        // 24-byte PRG-ROM in exponent form: 2^3 * 3
+        f.extend([0x0d, 0, 0x20, 0x48, 0x31, 0x0f, 0x07, 0x70, 1, 2, 0, 0]);
+        f.extend(vec![0xaa; 24]);
+        f.extend([1, 2, 3]);
+        let rom = NesRom::from_bytes(&f).unwrap();
+        assert_eq!(rom.header.mapper, 0x142);
+        assert_eq!(rom.header.submapper(), 3);
+        assert_eq!(rom.prg_rom.len(), 24);
+        assert_eq!(rom.misc, [1, 2, 3]);
+        assert_eq!(rom.to_bytes().unwrap(), f);
+    }
+    #[test]
+    fn nes2_exponent_size_kept() {
+        let mut f = MAGIC.to_vec();
+        // 32 KiB PRG-ROM as 2^15 * 1, which also fits in 16 KiB units
+        f.extend([0x3c, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0]);
+        f.extend(vec![0xea; 0x8000]);
+        let mut rom = NesRom::from_bytes(&f).unwrap();
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.to_bytes().unwrap(), f);
+        // a changed size is encoded afresh
+        rom.prg_rom.truncate(0x4000);
+        assert_eq!(rom.to_bytes().unwrap()[4..10], [1, 0, 0, 0x08, 0, 0]);
+    }
+    #[test]
+    fn prg_sections() {
+        let rom = NesRom::from_bytes(&ines(0, 2, 1, [0; 8])).unwrap();
+        let sections = rom.sections("6502");
+        let orgs: Vec<_> = sections.iter().map(|(s, _)| (s.org, s.bank)).collect();
+        assert_eq!(orgs, [(Some(0x8000), None), (Some(0xc000), None)]);
+        assert_eq!(sections[0].0.arch, "6502");
+        let rom = NesRom::from_bytes(&ines(0x10, 2, 1, [0; 8])).unwrap();
+        let banks: Vec<_> = rom
+            .sections("6502")
+            .iter()
+            .map(|(s, _)| (s.org, s.bank))
+            .collect();
+        assert_eq!(banks, [(None, Some(0)), (None, Some(1))]);
+    }
+    #[test]
+    fn malformed() {
+        let f = ines(0, 2, 1, [0; 8]);
+        assert_eq!(
+            NesRom::from_bytes(b"NOPE").unwrap_err(),
+            ErrorKind::BadMagic
+        );
+        assert_eq!(
+            NesRom::from_bytes(&f[..10]).unwrap_err(),
+            ErrorKind::Truncated
+        );
+        assert_eq!(
+            NesRom::from_bytes(&f[..f.len() - 1]).unwrap_err(),
+            ErrorKind::Truncated
+        );
+        let mut rom = NesRom::from_bytes(&f).unwrap();
+        rom.header.mapper = 0x100;
+        assert_eq!(rom.to_bytes().unwrap_err(), ErrorKind::Format);
+    }
+}
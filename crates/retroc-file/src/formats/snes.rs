@@ -0,0 +1,494 @@
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, TryAsRef};
+use alloc::{string::String, vec::Vec};
+
+/// Size of the header some copier devices put in front of the ROM.
+pub const COPIER_HEADER_LEN: usize = 512;
+const HEADER_LEN: usize = 0x40;
+
+/// How the cartridge ROM appears on the SNES bus.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum SnesMapping {
+    /// 32 KiB banks in the upper half of banks $80–$FF.
+    LoRom,
+    /// 64 KiB banks at $C0–$FF.
+    HiRom,
+    /// HiROM extended to 8 MiB, the second half at $40–$7D.
+    ExHiRom,
+}
+
+impl SnesMapping {
+    /// File offset of the internal header, which the CPU sees at $00FFC0.
+    pub fn header_offset(self) -> usize {
+        match self {
+            SnesMapping::LoRom => 0x7fc0,
+            SnesMapping::HiRom => 0xffc0,
+            SnesMapping::ExHiRom => 0x40ffc0,
+        }
+    }
+
+    /// Low nibble of the header's map mode byte for this mapping.
+    fn mode(self) -> u8 {
+        match self {
+            SnesMapping::LoRom => 0x0,
+            SnesMapping::HiRom => 0x1,
+            SnesMapping::ExHiRom => 0x5,
+        }
+    }
+
+    /// The bus address of a ROM offset, in the $80–$FF mirror where there is
+    /// one.
+    pub fn bus_address(self, offset: usize) -> Option<u32> {
+        let offset = u32::try_from(offset).ok()?;
+        match self {
+            SnesMapping::LoRom if offset < 0x40_0000 => {
+                Some(0x80_0000 | (offset >> 15) << 16 | 0x8000 | (offset & 0x7fff))
+            }
+            SnesMapping::HiRom | SnesMapping::ExHiRom if offset < 0x40_0000 => {
+                Some(0xc0_0000 | offset)
+            }
+            // banks $7E and $7F are work RAM
+            SnesMapping::ExHiRom if offset < 0x7e_0000 => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// The ROM offset a bus address reads, if it reads the ROM.
+    pub fn rom_offset(self, address: u32) -> Option<usize> {
+        let bank = (address >> 16) & 0xff;
+        let addr = address & 0xffff;
+        let offset = match (self, bank) {
+            (_, 0x7e | 0x7f) => return None,
+            (SnesMapping::LoRom, _) if addr >= 0x8000 => (bank & 0x7f) << 15 | (addr & 0x7fff),
+            (SnesMapping::LoRom, _) => return None,
+            (SnesMapping::HiRom, 0x40..=0x7d | 0xc0..=0xff) => (bank & 0x3f) << 16 | addr,
+            (SnesMapping::ExHiRom, 0xc0..=0xff) => (bank & 0x3f) << 16 | addr,
+            (SnesMapping::ExHiRom, 0x40..=0x7d) => address & 0x7f_ffff,
+            (_, _) if addr < 0x8000 => return None,
+            (SnesMapping::HiRom, _) => (bank & 0x3f) << 16 | addr,
+            (SnesMapping::ExHiRom, 0x80..=0xbf) => (bank & 0x3f) << 16 | addr,
+            (SnesMapping::ExHiRom, _) => 0x40_0000 | (bank & 0x3f) << 16 | addr,
+        };
+        Some(offset as usize)
+    }
+
+    /// Size of the ROM slices mapped contiguously.
+    pub fn bank_len(self) -> usize {
+        match self {
+            SnesMapping::LoRom => 0x8000,
+            SnesMapping::HiRom | SnesMapping::ExHiRom => 0x10000,
+        }
+    }
+
+    /// How plausible the header this mapping would use is, or `None` if the
+    /// ROM is too small to hold it.
+    ///
+    /// Each check that a real header passes adds to the score: a matching
+    /// checksum/complement pair, a map mode naming this mapping, a sensible
+    /// ROM size, a printable title, and a reset vector into ROM pointing at
+    /// a typical first instruction.
+    pub fn score(self, rom: &[u8]) -> Option<i32> {
+        let at = self.header_offset();
+        let h = rom.get(at..at + HEADER_LEN)?;
+        let word = |i: usize| u16::from_le_bytes([h[i], h[i + 1]]);
+        let mut score = 0;
+        if word(0x1c) ^ word(0x1e) == 0xffff {
+            score += 4;
+            if word(0x1e) == checksum(rom) {
+                score += 4;
+            }
+        }
+        if h[0x15] & 0x0f == self.mode() && h[0x15] & 0xe0 == 0x20 {
+            score += 2;
+        }
+        if (0x07..=0x0d).contains(&h[0x17]) {
+            score += 1;
+        }
+        if h[..21].iter().all(|&b| (0x20..0x7f).contains(&b)) {
+            score += 1;
+        }
+        let reset = word(0x3c);
+        if reset < 0x8000 {
+            return Some(score - 4);
+        }
+        let first = self
+            .rom_offset(reset as u32)
+            .and_then(|offset| rom.get(offset));
+        score += match first {
+            // SEI, CLC, SEP, REP, JMP, JML
+            Some(0x78 | 0x18 | 0xe2 | 0xc2 | 0x4c | 0x5c) => 2,
+            // BRK, COP, WDM, STP, SBC long,X: almost certainly not code
+            Some(0x00 | 0x02 | 0x42 | 0xdb | 0xff) | None => -4,
+            Some(_) => 0,
+        };
+        Some(score)
+    }
+
+    /// The best-scoring mapping for a ROM without copier header, with its
+    /// score.
+    pub fn detect(rom: &[u8]) -> Option<(Self, i32)> {
+        [SnesMapping::LoRom, SnesMapping::HiRom, SnesMapping::ExHiRom]
+            .into_iter()
+            .filter_map(|m| Some((m, m.score(rom)?)))
+            .fold(None, |best, (m, s)| match best {
+                Some((_, b)) if b >= s => best,
+                _ => Some((m, s)),
+            })
+    }
+}
+
+/// The 16-bit sum of the ROM, with a size that is not a power of two
+/// mirrored up to the next one as the cartridge does.
+pub fn checksum(rom: &[u8]) -> u16 {
+    if rom.is_empty() {
+        return 0;
+    }
+    let pow = 1 << rom.len().ilog2();
+    let (head, rest) = rom.split_at(pow);
+    let sum = head.iter().fold(0u16, |s, &b| s.wrapping_add(b as u16));
+    if rest.is_empty() {
+        return sum;
+    }
+    let repeats = (pow / rest.len().next_power_of_two()) as u16;
+    sum.wrapping_add(checksum(rest).wrapping_mul(repeats))
+}
+
+/// The internal header, at $FFC0–$FFDF as the CPU sees it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SnesHeader {
+    /// Title in ASCII (or JIS X 0201), padded with spaces.
+    pub title: [u8; 21],
+    /// Mapping in the low nibble, $10 set for FastROM.
+    pub map_mode: u8,
+    pub cartridge_type: u8,
+    /// ROM size as log2 of KiB.
+    pub rom_size: u8,
+    /// Cartridge RAM size as log2 of KiB, 0 for none.
+    pub ram_size: u8,
+    pub region: u8,
+    /// $33 announces the extended header at $FFB0.
+    pub developer: u8,
+    pub version: u8,
+    pub complement: u16,
+    pub checksum: u16,
+}
+
+impl SnesHeader {
+    /// The title up to its space padding.
+    pub fn title(&self) -> &[u8] {
+        let end = self
+            .title
+            .iter()
+            .rposition(|&b| b != b' ' && b != 0)
+            .map_or(0, |i| i + 1);
+        &self.title[..end]
+    }
+
+    pub fn fast_rom(&self) -> bool {
+        self.map_mode & 0x10 != 0
+    }
+
+    pub fn rom_len(&self) -> usize {
+        0x400 << self.rom_size.min(16)
+    }
+
+    pub fn ram_len(&self) -> usize {
+        match self.ram_size {
+            0 => 0,
+            n => 0x400 << n.min(16),
+        }
+    }
+}
+
+/// The interrupt vectors at $FFE0–$FFFF.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct SnesVectors {
+    pub cop: u16,
+    pub brk: u16,
+    pub abort: u16,
+    pub nmi: u16,
+    pub irq: u16,
+    /// Emulation mode vectors.
+    pub emu_cop: u16,
+    pub emu_abort: u16,
+    pub emu_nmi: u16,
+    pub reset: u16,
+    /// Emulation mode IRQ and BRK.
+    pub emu_irq: u16,
+}
+
+/// A SNES cartridge image.
+///
+/// `data` is the ROM without any copier header; the internal header is
+/// decoded from it according to `mapping` and written back over it by
+/// [`SnesRom::repair`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SnesRom {
+    /// The 512-byte copier header, if the file had one.
+    pub copier: Option<Vec<u8>>,
+    pub mapping: SnesMapping,
+    pub header: SnesHeader,
+    pub data: Vec<u8>,
+}
+
+impl SnesRom {
+    /// Parse a `.sfc` / `.smc` file, splitting off a copier header if the
+    /// size says there is one and locating the internal header by score.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErrorKind> {
+        let (copier, rom) = if bytes.len() % 0x400 == COPIER_HEADER_LEN {
+            let (copier, rom) = bytes.split_at(COPIER_HEADER_LEN);
+            (Some(copier.to_vec()), rom)
+        } else {
+            (None, bytes)
+        };
+        let (mapping, _) = SnesMapping::detect(rom).ok_or(ErrorKind::Truncated)?;
+        Ok(Self::with_mapping(copier, mapping, rom))
+    }
+
+    /// Take a ROM without copier header whose mapping is already known.
+    pub fn from_rom(rom: &[u8], mapping: SnesMapping) -> Result<Self, ErrorKind> {
+        mapping.score(rom).ok_or(ErrorKind::Truncated)?;
+        Ok(Self::with_mapping(None, mapping, rom))
+    }
+
+    fn with_mapping(copier: Option<Vec<u8>>, mapping: SnesMapping, rom: &[u8]) -> Self {
+        let at = mapping.header_offset();
+        let h = &rom[at..at + HEADER_LEN];
+        let header = SnesHeader {
+            title: h[..21].try_into().unwrap(),
+            map_mode: h[0x15],
+            cartridge_type: h[0x16],
+            rom_size: h[0x17],
+            ram_size: h[0x18],
+            region: h[0x19],
+            developer: h[0x1a],
+            version: h[0x1b],
+            complement: u16::from_le_bytes([h[0x1c], h[0x1d]]),
+            checksum: u16::from_le_bytes([h[0x1e], h[0x1f]]),
+        };
+        Self {
+            copier,
+            mapping,
+            header,
+            data: rom.to_vec(),
+        }
+    }
+
+    pub fn vectors(&self) -> SnesVectors {
+        let at = self.mapping.header_offset();
+        let word = |i: usize| u16::from_le_bytes([self.data[at + i], self.data[at + i + 1]]);
+        SnesVectors {
+            cop: word(0x24),
+            brk: word(0x26),
+            abort: word(0x28),
+            nmi: word(0x2a),
+            irq: word(0x2e),
+            emu_cop: word(0x34),
+            emu_abort: word(0x38),
+            emu_nmi: word(0x3a),
+            reset: word(0x3c),
+            emu_irq: word(0x3e),
+        }
+    }
+
+    pub fn checksum_ok(&self) -> bool {
+        self.header.complement ^ self.header.checksum == 0xffff
+            && self.header.checksum == checksum(&self.data)
+    }
+
+    /// The bus address of an offset into `data`.
+    pub fn bus_address(&self, offset: usize) -> Option<u32> {
+        self.mapping.bus_address(offset)
+    }
+
+    /// The offset into `data` a bus address reads.
+    pub fn rom_offset(&self, address: u32) -> Option<usize> {
+        self.mapping
+            .rom_offset(address)
+            .filter(|&offset| offset < self.data.len())
+    }
+
+    /// Write the header fields back into the ROM and recompute the checksum
+    /// and its complement, updating them in `header` too.
+    pub fn repair(&mut self) {
+        let h = &self.header;
+        let at = self.mapping.header_offset();
+        let d = &mut self.data[at..at + HEADER_LEN];
+        d[..21].copy_from_slice(&h.title);
+        d[0x15] = h.map_mode;
+        d[0x16] = h.cartridge_type;
+        d[0x17] = h.rom_size;
+        d[0x18] = h.ram_size;
+        d[0x19] = h.region;
+        d[0x1a] = h.developer;
+        d[0x1b] = h.version;
+        // a valid pair always adds $1FE, whatever its value
+        d[0x1c..0x20].copy_from_slice(&[0xff, 0xff, 0x00, 0x00]);
+        let sum = checksum(&self.data);
+        self.header.checksum = sum;
+        self.header.complement = !sum;
+        let d = &mut self.data[at..at + HEADER_LEN];
+        d[0x1c..0x1e].copy_from_slice(&(!sum).to_le_bytes());
+        d[0x1e..0x20].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    /// The repaired file, with the copier header if there is one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rom = self.clone();
+        rom.repair();
+        let mut out = rom.copier.unwrap_or_default();
+        out.extend(rom.data);
+        out
+    }
+
+    /// Each contiguously mapped slice of the ROM as a section originating
+    /// at its bus address, with the bank byte as its bank.
+    pub fn sections(&self, arch: &str) -> Vec<(Section, MemoryImage)> {
+        let bank_len = self.mapping.bank_len();
+        self.data
+            .chunks(bank_len)
+            .enumerate()
+            .map(|(i, data)| {
+                let mut section = Section::new(arch);
+                if let Some(address) = self.bus_address(i * bank_len) {
+                    section.org = Some(address as u64);
+                    section.bank = Some((address >> 16) as u64);
+                }
+                let image = MemoryImage::from_bytes(section.org.unwrap_or(0), data);
+                (section, image)
+            })
+            .collect()
+    }
+}
+
+/// The SNES cartridge format, taking the whole remaining byte stream as one
+/// `.sfc` / `.smc` file. Writing repairs the checksum.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnesParser {
+    /// Leave out the copier header when writing.
+    pub strip_copier: bool,
+}
+
+impl<T: From<SnesRom>, Err: From<ErrorKind>> FileParser<T, Err> for SnesParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let rom = SnesRom::from_bytes(bytes).map_err(|e| nom::Err::Error(Err::from(e)))?;
+        Ok((&bytes[bytes.len()..], meta, T::from(rom)))
+    }
+    fn probe(&self, bytes: &[u8], extension: Option<&str>) -> Option<u32> {
+        let rom = match bytes.len() % 0x400 {
+            COPIER_HEADER_LEN => &bytes[COPIER_HEADER_LEN..],
+            _ => bytes,
+        };
+        let (_, score) = SnesMapping::detect(rom)?;
+        if score < 4 {
+            return None;
+        }
+        let mut confidence = 40 + 5 * score as u32;
+        if matches!(extension, Some("sfc" | "smc")) {
+            confidence += 20;
+        }
+        Some(confidence)
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("SNES cartridge"))
+    }
+}
+
+impl<T: TryAsRef<SnesRom>, Err: From<ErrorKind>> FileWriter<T, Err> for SnesParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        _meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let rom = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        let out = rom.to_bytes();
+        match (&rom.copier, self.strip_copier) {
+            (Some(copier), true) => bytes.extend_from_slice(&out[copier.len()..]),
+            _ => bytes.extend(out),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn make(len: usize, at: usize, mode: u8, code_at: usize) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..len).map(|i| (i * 7) as u8 | 1).collect();
+        let h = &mut rom[at..at + HEADER_LEN];
+        h[..21].copy_from_slice(b"TEST GAME            ");
+        h[0x15] = mode;
+        h[0x17] = 0x09;
+        h[0x3c..0x3e].copy_from_slice(&0x8000u16.to_le_bytes());
+        rom[code_at] = 0x78;
+        rom
+    }
+
+    #[test]
+    fn lorom_round_trip() {
+        let mut rom =
+            SnesRom::from_rom(&make(0x80000, 0x7fc0, 0x20, 0), SnesMapping::LoRom).unwrap();
+        assert_eq!(rom.header.title(), b"TEST GAME");
+        assert!(!rom.checksum_ok());
+        rom.repair();
+        let bytes = rom.to_bytes();
+        let back = SnesRom::from_bytes(&bytes).unwrap();
+        assert_eq!(back.mapping, SnesMapping::LoRom);
+        assert!(back.checksum_ok());
+        assert_eq!(back.vectors().reset, 0x8000);
+        assert_eq!(back.bus_address(0x7fc0), Some(0x80ffc0));
+        assert_eq!(back.rom_offset(0x018000), Some(0x8000));
+        assert_eq!(back.rom_offset(0x7e8000), None);
+
+        let mut copied = vec![0u8; COPIER_HEADER_LEN];
+        copied.extend(&bytes);
+        let copied_rom = SnesRom::from_bytes(&copied).unwrap();
+        assert!(copied_rom.copier.is_some() && copied_rom.checksum_ok());
+        assert_eq!(copied_rom.to_bytes(), copied);
+        let sections = copied_rom.sections("65816");
+        assert_eq!(sections.len(), 16);
+        assert_eq!(
+            (sections[1].0.org, sections[1].0.bank),
+            (Some(0x818000), Some(0x81))
+        );
+    }
+    #[test]
+    fn hirom_mirrored_checksum() {
+        let mut rom =
+            SnesRom::from_rom(&make(0x60000, 0xffc0, 0x21, 0x8000), SnesMapping::HiRom).unwrap();
+        rom.repair();
+        let back = SnesRom::from_bytes(&rom.to_bytes()).unwrap();
+        assert_eq!(back.mapping, SnesMapping::HiRom);
+        assert!(back.checksum_ok());
+        assert_eq!(back.rom_offset(0xc12345), Some(0x12345));
+        let sum = |s: &[u8]| s.iter().fold(0u16, |a, &b| a.wrapping_add(b as u16));
+        let data = &back.data;
+        assert_eq!(
+            checksum(data),
+            sum(&data[..0x40000]).wrapping_add(sum(&data[0x40000..]).wrapping_mul(2))
+        );
+    }
+    #[test]
+    fn malformed_rom() {
+        assert_eq!(
+            SnesRom::from_bytes(&[0; 0x100]).unwrap_err(),
+            ErrorKind::Truncated
+        );
+        assert_eq!(
+            SnesRom::from_rom(&[0; 0x8000], SnesMapping::HiRom).unwrap_err(),
+            ErrorKind::Truncated
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use super::{decode_hex, next_line, write_hex};
+use crate::image::MemoryImage;
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter, Locate, TryAsRef};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use nom::error::ErrorKind as NomKind;
+
+/// The contents of a Motorola S-record file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SRecords {
+    /// Data of the S0 header record.
+    pub header: Vec<u8>,
+    pub image: MemoryImage,
+    /// Start address from the S7, S8 or S9 termination record. The record
+    /// has no way to leave it out, so an address of 0 reads back as `None`.
+    pub start: Option<u32>,
+}
+
+impl From<SRecords> for MemoryImage {
+    fn from(srec: SRecords) -> Self {
+        srec.image
+    }
+}
+
+/// Address bytes in each record type.
+fn address_len(kind: u8) -> Option<usize> {
+    match kind {
+        b'0' | b'1' | b'5' | b'9' => Some(2),
+        b'2' | b'6' | b'8' => Some(3),
+        b'3' | b'7' => Some(4),
+        _ => None,
+    }
+}
+
+/// Parse S-records up to and including a termination record, or up to the
+/// first line that is not a record, returning the text after them.
+///
+/// S5/S6 record counts are checked against the data records read so far.
+/// Errors are raised at the offending record.
+pub fn parse_srec<Err: From<ErrorKind> + Locate>(text: &str) -> Result<(&str, SRecords), Err> {
+    let mut out = SRecords::default();
+    let mut records: u64 = 0;
+    let mut rest = text;
+    loop {
+        let at = rest;
+        let fail = |kind| Err::from(kind).at_meta(at);
+        let Some((line, next)) = next_line(rest) else {
+            return Ok((rest, out));
+        };
+        if line.is_empty() {
+            rest = next;
+            continue;
+        }
+        let Some(record) = line.strip_prefix('S') else {
+            return Ok((at, out));
+        };
+        rest = next;
+        let kind = *record
+            .as_bytes()
+            .first()
+            .ok_or_else(|| fail(ErrorKind::Nom(NomKind::LengthValue)))?;
+        let addr_len = address_len(kind).ok_or_else(|| fail(ErrorKind::Corrupt))?;
+        let bytes =
+            decode_hex(&record[1..]).ok_or_else(|| fail(ErrorKind::Nom(NomKind::HexDigit)))?;
+        if bytes.len() < 2 + addr_len || bytes.len() != 1 + bytes[0] as usize {
+            return Err(fail(ErrorKind::Nom(NomKind::LengthValue)));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0xff {
+            return Err(fail(ErrorKind::Checksum));
+        }
+        let address = bytes[1..1 + addr_len]
+            .iter()
+            .fold(0u32, |a, &b| (a << 8) | b as u32);
+        let data = &bytes[1 + addr_len..bytes.len() - 1];
+        match kind {
+            b'0' => out.header = data.to_vec(),
+            b'1'..=b'3' => {
+                out.image.insert(address as u64, data).map_err(fail)?;
+                records += 1;
+            }
+            b'5' | b'6' if address as u64 != records => return Err(fail(ErrorKind::Corrupt)),
+            b'5' | b'6' => {}
+            _ => {
+                out.start = (address != 0).then_some(address);
+                return Ok((rest, out));
+            }
+        }
+    }
+}
+
+fn write_record<W: Write>(w: &mut W, kind: u8, address: &[u8], data: &[u8]) -> core::fmt::Result {
+    let count = (address.len() + data.len() + 1) as u8;
+    let sum = address
+        .iter()
+        .chain(data)
+        .fold(count, |sum, &b| sum.wrapping_add(b));
+    write!(w, "S{}", kind)?;
+    write_hex(w, &[count])?;
+    write_hex(w, address)?;
+    write_hex(w, data)?;
+    write_hex(w, &[!sum])?;
+    w.write_char('\n')
+}
+
+/// Write `image` as S-records with up to `record_len` data bytes per
+/// record: an S0 `header`, data records with the narrowest addresses that
+/// fit the image and `start`, a record count, and a termination record
+/// holding `start` (or 0).
+///
+/// Images reaching past 4 GiB, headers too long for one record and record
+/// lengths that do not fit are [`ErrorKind::Format`] errors.
+pub fn write_srec<W: Write>(
+    w: &mut W,
+    image: &MemoryImage,
+    header: &[u8],
+    start: Option<u32>,
+    record_len: usize,
+) -> Result<(), ErrorKind> {
+    let end = image.end().unwrap_or(0);
+    let start_at = start.unwrap_or(0) as u64;
+    let (data_kind, end_kind, addr_len) = if end <= 1 << 16 && start_at < 1 << 16 {
+        (1, 9, 2)
+    } else if end <= 1 << 24 && start_at < 1 << 24 {
+        (2, 8, 3)
+    } else if end <= 1 << 32 {
+        (3, 7, 4)
+    } else {
+        return Err(ErrorKind::Format);
+    };
+    let max_len = 255 - addr_len - 1;
+    if !(1..=max_len).contains(&record_len) || header.len() > 255 - 3 {
+        return Err(ErrorKind::Format);
+    }
+    let fmt = |_| ErrorKind::Format;
+    let address = |a: u64| a.to_be_bytes()[8 - addr_len..].to_vec();
+
+    write_record(w, 0, &[0, 0], header).map_err(fmt)?;
+    let mut records: u64 = 0;
+    for (start, data) in image.segments() {
+        for (i, chunk) in data.chunks(record_len).enumerate() {
+            let at = start + (i * record_len) as u64;
+            write_record(w, data_kind, &address(at), chunk).map_err(fmt)?;
+            records += 1;
+        }
+    }
+    if records < 1 << 16 {
+        write_record(w, 5, &(records as u16).to_be_bytes(), &[]).map_err(fmt)?;
+    } else if records < 1 << 24 {
+        write_record(w, 6, &records.to_be_bytes()[5..], &[]).map_err(fmt)?;
+    }
+    write_record(w, end_kind, &address(start_at), &[]).map_err(fmt)
+}
+
+/// The Motorola S-record format, read from a section's metadata text.
+///
+/// Works with any registry whose values can be made from [`SRecords`], and
+/// writes those that hold them.
+#[derive(Clone, Copy, Debug)]
+pub struct SrecParser {
+    /// Data bytes per record when writing.
+    pub record_len: usize,
+}
+
+impl Default for SrecParser {
+    fn default() -> Self {
+        Self { record_len: 16 }
+    }
+}
+
+impl<T: From<SRecords>, Err: From<ErrorKind> + Locate> FileParser<T, Err> for SrecParser {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        let (meta, srec) = parse_srec(meta).map_err(nom::Err::Error)?;
+        Ok((bytes, meta, T::from(srec)))
+    }
+    fn describe(&self) -> Option<String> {
+        Some(String::from("Motorola S-records"))
+    }
+}
+
+impl<T: TryAsRef<SRecords>, Err: From<ErrorKind>> FileWriter<T, Err> for SrecParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        _bytes: &mut Vec<u8>,
+        meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        let srec = value.try_as_ref().ok_or(ErrorKind::WrongValue)?;
+        write_srec(meta, &srec.image, &srec.header, srec.start, self.record_len)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = "S00F000068656C6C6F202020202000003C\nS11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\nS5030001FB\nS9030000FC\n";
+        let (rest, srec) = parse_srec::<ErrorKind>(text).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(&srec.header[..5], b"hello");
+        assert_eq!(srec.image.get(0), Some(0x7c));
+        assert_eq!(srec.start, None);
+        let mut out = String::new();
+        write_srec(&mut out, &srec.image, &srec.header, srec.start, 28).unwrap();
+        assert_eq!(out, text);
+    }
+    #[test]
+    fn wide_addresses() {
+        let image = MemoryImage::from_bytes(0x123456, &[1, 2, 3]);
+        let mut out = String::new();
+        write_srec(&mut out, &image, b"", Some(0x123456), 16).unwrap();
+        assert!(out.contains("\nS2") && out.contains("\nS8"));
+        let (_, srec) = parse_srec::<ErrorKind>(&out).unwrap();
+        assert_eq!(srec.image, image);
+        assert_eq!(srec.start, Some(0x123456));
+    }
+    #[test]
+    fn registry_round_trip() {
+        let parser = SrecParser::default();
+        let mut registry: FileRegistry<SRecords, ErrorKind> = FileRegistry::default();
+        registry.register_format("srec".into(), &parser);
+        let srec = SRecords {
+            header: b"demo".to_vec(),
+            image: MemoryImage::from_bytes(0x8000, &[1, 2, 3]),
+            start: Some(0x8000),
+        };
+        let sections = [(Section::new("srec"), srec)];
+        let (mut bytes, mut meta) = (Vec::new(), String::new());
+        registry
+            .write_file(&sections, &mut bytes, &mut meta)
+            .unwrap();
+        let (_, rest, back) = registry.parse_sections(&bytes, &meta).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(back, sections);
+    }
+    #[test]
+    fn no_start_address() {
+        let image = MemoryImage::from_bytes(0x10, &[1]);
+        for start in [None, Some(0)] {
+            let mut out = String::new();
+            write_srec(&mut out, &image, b"", start, 16).unwrap();
+            assert!(out.ends_with("S9030000FC\n"));
+            assert_eq!(parse_srec::<ErrorKind>(&out).unwrap().1.start, None);
+        }
+    }
+    #[test]
+    fn malformed() {
+        // record count of 2 after no data records
+        assert_eq!(
+            parse_srec::<ErrorKind>("S5030002FA\n").unwrap_err(),
+            ErrorKind::Corrupt
+        );
+        assert_eq!(
+            parse_srec::<ErrorKind>("S9030000FD\n").unwrap_err(),
+            ErrorKind::Checksum
+        );
+        assert_eq!(
+            parse_srec::<ErrorKind>("S4030000FC\n").unwrap_err(),
+            ErrorKind::Corrupt
+        );
+        assert_eq!(
+            parse_srec::<ErrorKind>("S9040000FC\n").unwrap_err(),
+            ErrorKind::Nom(NomKind::LengthValue)
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use crate::ErrorKind;
+use crate::listing::core::{ListingConfig, ListingEntry};
+use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
+
+/// A sparse memory image: runs of bytes at addresses, with gaps between
+/// them read back as `fill`.
+///
+/// Adjacent runs are merged on insertion. Writing over bytes already in the
+/// image is allowed only if the bytes are the same; otherwise insertion fails
+/// with [`ErrorKind::Overlap`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryImage {
+    segments: BTreeMap<u64, Vec<u8>>,
+    /// Byte used for addresses not covered by any segment.
+    pub fill: u8,
+}
+
+impl MemoryImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_fill(fill: u8) -> Self {
+        Self {
+            segments: BTreeMap::new(),
+            fill,
+        }
+    }
+
+    /// An image holding `bytes` at `address`.
+    pub fn from_bytes(address: u64, bytes: &[u8]) -> Self {
+        let mut image = Self::new();
+        if !bytes.is_empty() {
+            image.segments.insert(address, bytes.to_vec());
+        }
+        image
+    }
+
+    /// Build an image from listing entries. Entries without an address
+    /// carry no bytes and are skipped.
+    pub fn from_entries(entries: &[ListingEntry]) -> Result<Self, ErrorKind> {
+        let mut image = Self::new();
+        for e in entries {
+            if let Some(address) = e.address {
+                image.insert(address, &e.bytes)?;
+            }
+        }
+        Ok(image)
+    }
+
+    /// Place `bytes` at `address`, merging with neighbouring segments.
+    ///
+    /// On a conflict the image is left unchanged and the first conflicting
+    /// address is reported.
+    pub fn insert(&mut self, address: u64, bytes: &[u8]) -> Result<(), ErrorKind> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let end = address
+            .checked_add(bytes.len() as u64)
+            .ok_or(ErrorKind::Overlap(address))?;
+
+        // Segments touching [address, end], including those just adjacent.
+        let touching: Vec<u64> = self
+            .segments
+            .range(..=end)
+            .rev()
+            .take_while(|(start, data)| *start + data.len() as u64 >= address)
+            .map(|(start, _)| *start)
+            .collect();
+
+        for &start in &touching {
+            let data = &self.segments[&start];
+            let lo = start.max(address);
+            let hi = (start + data.len() as u64).min(end);
+            for a in lo..hi {
+                if data[(a - start) as usize] != bytes[(a - address) as usize] {
+                    return Err(ErrorKind::Overlap(a));
+                }
+            }
+        }
+
+        let mut merged_start = address;
+        let mut merged_end = end;
+        for &start in &touching {
+            merged_start = merged_start.min(start);
+            merged_end = merged_end.max(start + self.segments[&start].len() as u64);
+        }
+        let mut merged = alloc::vec![0u8; (merged_end - merged_start) as usize];
+        for start in touching {
+            let data = self.segments.remove(&start).unwrap();
+            let at = (start - merged_start) as usize;
+            merged[at..at + data.len()].copy_from_slice(&data);
+        }
+        let at = (address - merged_start) as usize;
+        merged[at..at + bytes.len()].copy_from_slice(bytes);
+        self.segments.insert(merged_start, merged);
+        Ok(())
+    }
+
+    /// The byte at `address`, if one has been placed there.
+    pub fn get(&self, address: u64) -> Option<u8> {
+        let (start, data) = self.segments.range(..=address).next_back()?;
+        data.get((address - start) as usize).copied()
+    }
+
+    /// The contiguous runs of bytes in ascending address order.
+    pub fn segments(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.segments.iter().map(|(a, d)| (*a, d.as_slice()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Lowest address holding data.
+    pub fn start(&self) -> Option<u64> {
+        self.segments.keys().next().copied()
+    }
+
+    /// One past the highest address holding data.
+    pub fn end(&self) -> Option<u64> {
+        let (start, data) = self.segments.iter().next_back()?;
+        Some(start + data.len() as u64)
+    }
+
+    /// Flatten the image into bytes starting at `base`, filling gaps with
+    /// `fill`. Data below `base` is dropped.
+    pub fn to_bytes(&self, base: u64) -> Vec<u8> {
+        let end = self.end().unwrap_or(base).max(base);
+        let mut out = alloc::vec![self.fill; (end - base) as usize];
+        for (start, data) in self.segments() {
+            let skip = base.saturating_sub(start) as usize;
+            if skip >= data.len() {
+                continue;
+            }
+            let at = (start + skip as u64 - base) as usize;
+            out[at..at + data.len() - skip].copy_from_slice(&data[skip..]);
+        }
+        out
+    }
+
+    /// Split the image into listing entries of `cfg.entry_bytes()` bytes each,
+    /// with empty text. Entries never span a gap.
+    pub fn to_entries(&self, cfg: ListingConfig) -> Vec<ListingEntry> {
+        let n = cfg.entry_bytes().max(1);
+        let mut out = Vec::new();
+        for (start, data) in self.segments() {
+            for (i, chunk) in data.chunks(n).enumerate() {
+                out.push(ListingEntry {
+                    address: Some(start + (i * n) as u64),
+                    bytes: chunk.to_vec(),
+                    text: String::new(),
+                });
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn merges_adjacent_runs() {
+        let mut image = MemoryImage::with_fill(0xff);
+        image.insert(4, &[1, 2]).unwrap();
+        image.insert(0, &[9]).unwrap();
+        image.insert(6, &[3]).unwrap();
+        assert_eq!(image.segments().count(), 2);
+        // the same bytes may be written again
+        image.insert(5, &[2, 3]).unwrap();
+        assert_eq!(image.to_bytes(0), [9, 0xff, 0xff, 0xff, 1, 2, 3]);
+        // bridging the gap joins everything into one run
+        image.insert(1, &[8, 8, 8]).unwrap();
+        let segments: Vec<_> = image.segments().collect();
+        assert_eq!(segments, [(0, &[9, 8, 8, 8, 1, 2, 3][..])]);
+    }
+    #[test]
+    fn overlap_leaves_image_unchanged() {
+        let mut image = MemoryImage::from_bytes(0x10, &[1, 2, 3]);
+        image.insert(0x20, &[4]).unwrap();
+        let before = image.clone();
+        // the first differing address is reported, across gaps too
+        assert_eq!(
+            image.insert(0x0f, &[0, 1, 5, 6]),
+            Err(ErrorKind::Overlap(0x11))
+        );
+        assert_eq!(
+            image.insert(0x12, &[3; 0x0f]),
+            Err(ErrorKind::Overlap(0x20))
+        );
+        assert_eq!(
+            image.insert(u64::MAX, &[1, 2]),
+            Err(ErrorKind::Overlap(u64::MAX))
+        );
+        assert_eq!(image, before);
+    }
+    #[test]
+    fn conflicting_entries() {
+        let entry = |address, bytes| ListingEntry {
+            address,
+            bytes,
+            text: String::new(),
+        };
+        let entries = [
+            entry(Some(2), vec![1, 2]),
+            entry(None, vec![]),
+            entry(Some(3), vec![2]),
+        ];
+        let image = MemoryImage::from_entries(&entries).unwrap();
+        assert_eq!((image.start(), image.end()), (Some(2), Some(4)));
+        let entries = [entry(Some(2), vec![1, 2]), entry(Some(3), vec![5])];
+        assert_eq!(
+            MemoryImage::from_entries(&entries),
+            Err(ErrorKind::Overlap(3))
+        );
+    }
+}
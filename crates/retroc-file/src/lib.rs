@@ -2,34 +2,139 @@
 
 use core::fmt::Display;
 
-use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
-use nom::error::ParseError;
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use nom::error::{ContextError, ParseError};
+use section::Section;
 extern crate alloc;
+/// The bytes and metadata left over from parsing, along with what was parsed.
+pub type ParseResult<'a, 'b, T, Err> = Result<(&'a [u8], &'b str, T), nom::Err<Err>>;
 pub trait ByteMetaParser<T, ParseErrType> {
     fn from_bytes_and_meta<'a, 'b>(
         &self,
         bytes: &'a [u8],
         meta: &'b str,
     ) -> Result<(&'a [u8], &'b str, T), nom::Err<ParseErrType>>;
+    /// A short human-readable description of the format.
+    fn describe(&self) -> Option<String> {
+        None
+    }
+}
+/// The writing counterpart of [`ByteMetaParser`].
+pub trait ByteMetaWriter<T, WriteErrType> {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+    ) -> Result<(), WriteErrType>;
 }
 pub trait FileParser<T, ParseErrType> {
     fn from_bytes_and_meta<'a, 'b>(
         &self,
         bytes: &'a [u8],
         meta: &'b str,
+        section: &Section,
         registry: &FileRegistry<'_, T, ParseErrType>,
     ) -> Result<(&'a [u8], &'b str, T), nom::Err<ParseErrType>>;
+    /// How confident this parser is that `bytes`, from a file with the given
+    /// `extension`, are in its format, for parsing files that have no `arch`
+    /// header. `None` rules the format out; higher is more confident. A
+    /// matching magic number or checksum should outrank an extension or size.
+    fn probe(&self, _bytes: &[u8], _extension: Option<&str>) -> Option<u32> {
+        None
+    }
+    /// A short human-readable description of the format.
+    fn describe(&self) -> Option<String> {
+        None
+    }
+}
+pub trait FileWriter<T, WriteErrType> {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+        section: &Section,
+        registry: &FileRegistry<'_, T, WriteErrType>,
+    ) -> Result<(), WriteErrType>;
+}
+/// Borrow a value as a `U` if it holds one.
+///
+/// Lets a writer for one format be registered in a registry whose values can
+/// hold several, such as an application's own enum of file contents.
+pub trait TryAsRef<U> {
+    fn try_as_ref(&self) -> Option<&U>;
+}
+impl<U> TryAsRef<U> for U {
+    fn try_as_ref(&self) -> Option<&U> {
+        Some(self)
+    }
+}
+/// Adapts a [`ByteMetaParser`] or [`ByteMetaWriter`], which need neither the
+/// section header nor the registry, into a [`FileParser`] or [`FileWriter`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ByteMeta<P>(pub P);
+impl<T, Err, P: ByteMetaParser<T, Err>> FileParser<T, Err> for ByteMeta<P> {
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(&'a [u8], &'b str, T), nom::Err<Err>> {
+        self.0.from_bytes_and_meta(bytes, meta)
+    }
+    fn describe(&self) -> Option<String> {
+        self.0.describe()
+    }
+}
+impl<T, Err, P: ByteMetaWriter<T, Err>> FileWriter<T, Err> for ByteMeta<P> {
+    fn to_bytes_and_meta(
+        &self,
+        value: &T,
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, T, Err>,
+    ) -> Result<(), Err> {
+        self.0.to_bytes_and_meta(value, bytes, meta)
+    }
 }
 pub struct FileRegistry<'a, T, Err> {
     pub parsers: BTreeMap<String, &'a (dyn FileParser<T, Err> + 'a)>,
+    pub writers: BTreeMap<String, &'a (dyn FileWriter<T, Err> + 'a)>,
 }
 impl<'a, T, Err> Default for FileRegistry<'a, T, Err> {
     fn default() -> Self {
         Self {
             parsers: BTreeMap::new(),
+            writers: BTreeMap::new(),
         }
     }
 }
+/// Errors that can record where in a file they happened.
+pub trait Locate {
+    /// Note the metadata left at the point the error was raised.
+    fn at_meta(self, _rest: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+    /// Resolve recorded positions against the full `meta` and `bytes`
+    /// streams, within the section named `arch`.
+    fn locate(self, _meta: &str, _bytes: &[u8], _arch: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
 impl<'a, T, Err: From<ErrorKind>> FileRegistry<'a, T, Err> {
     pub fn register_parser(
         &mut self,
@@ -38,30 +143,250 @@ impl<'a, T, Err: From<ErrorKind>> FileRegistry<'a, T, Err> {
     ) {
         self.parsers.insert(extension, parser);
     }
+    pub fn register_writer(
+        &mut self,
+        extension: String,
+        writer: &'a (dyn FileWriter<T, Err> + 'a),
+    ) {
+        self.writers.insert(extension, writer);
+    }
+    /// Register a format that can both be parsed and written under one name.
+    pub fn register_format<P: FileParser<T, Err> + FileWriter<T, Err>>(
+        &mut self,
+        extension: String,
+        format: &'a P,
+    ) {
+        self.parsers.insert(extension.clone(), format);
+        self.writers.insert(extension, format);
+    }
+    /// The names of every registered parser and writer.
+    pub fn arches(&self) -> Vec<&str> {
+        arch_names(self.parsers.keys().chain(self.writers.keys()))
+    }
+    /// The description of the parser registered as `arch`, if it has one.
+    pub fn describe(&self, arch: &str) -> Option<String> {
+        self.parsers.get(arch)?.describe()
+    }
+    /// Rank the registered parsers that accept `bytes` by confidence, most
+    /// confident first.
+    pub fn detect(&self, bytes: &[u8], extension: Option<&str>) -> Vec<(&str, u32)> {
+        rank(
+            self.parsers
+                .iter()
+                .map(|(arch, p)| (arch, p.probe(bytes, extension))),
+        )
+    }
+    /// Write `sections` with their headers, the inverse of `parse_sections`.
+    pub fn write_file(
+        &self,
+        sections: &[(Section, T)],
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+    ) -> Result<(), Err> {
+        for (section, t) in sections {
+            let Some(x) = self.writers.get(&section.arch).cloned() else {
+                return Err(Err::from(ErrorKind::NoWriter));
+            };
+            section
+                .write_header(meta)
+                .map_err(|_| Err::from(ErrorKind::Format))?;
+            x.to_bytes_and_meta(t, bytes, meta, section, self)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parsing needs errors that can [`Locate`] themselves in the file, which
+/// registering and writing do not.
+impl<'a, T, Err: From<ErrorKind> + Locate> FileRegistry<'a, T, Err> {
     pub fn parse_file<'a2, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+    ) -> ParseResult<'a, 'b, Vec<T>, Err> {
+        let (bytes, meta, sections) = self.parse_sections(bytes, meta)?;
+        Ok((bytes, meta, sections.into_iter().map(|(_, t)| t).collect()))
+    }
+    /// Parse every section along with the header it was read from.
+    pub fn parse_sections<'b>(
         &self,
         mut bytes: &'a [u8],
         mut meta: &'b str,
-    ) -> Result<(&'a [u8], &'b str, Vec<T>), nom::Err<Err>> {
+    ) -> ParseResult<'a, 'b, Vec<(Section, T)>, Err> {
+        let (all_bytes, all_meta) = (bytes, meta);
         let mut all = Vec::default();
         loop {
-            meta = match meta.strip_prefix("arch ") {
-                Some(rest) => rest,
-                None => return Ok((bytes, meta, all)),
-            };
-            let x;
-            (meta, x) = match meta.split_once('\n') {
-                Some((a, b)) => (b, a),
-                None => return Err(nom::Err::Error(Err::from(ErrorKind::ArchIncomplete))),
+            let header = meta;
+            let section;
+            (meta, section) = match section::parse_header(meta) {
+                Ok(Some(s)) => s,
+                Ok(None) => return Ok((bytes, meta, all)),
+                Err((kind, at, arch)) => {
+                    return Err(nom::Err::Error(
+                        Err::from(kind)
+                            .at_meta(at)
+                            .locate(all_meta, all_bytes, arch),
+                    ));
+                }
             };
-            let Some(x) = self.parsers.get(x).cloned() else {
-                return Err(nom::Err::Error(Err::from(ErrorKind::NoParser)));
+            let arch = section.arch.as_str();
+            let Some(x) = self.parsers.get(arch).cloned() else {
+                return Err(nom::Err::Error(
+                    Err::from(ErrorKind::NoParser)
+                        .at_meta(header)
+                        .locate(all_meta, all_bytes, arch),
+                ));
             };
             let t;
-            (bytes, meta, t) = x.from_bytes_and_meta(bytes, meta, self)?;
-            all.push(t);
+            (bytes, meta, t) = x
+                .from_bytes_and_meta(bytes, meta, &section, self)
+                .map_err(|e| e.map(|e| e.locate(all_meta, all_bytes, arch)))?;
+            all.push((section, t));
         }
     }
+    /// Parse a bare file with no metadata, trying the parsers `detect`
+    /// ranks in turn until one succeeds.
+    ///
+    /// Returns the error of the most confident parser if none succeed.
+    pub fn parse_detected(
+        &self,
+        bytes: &'a [u8],
+        extension: Option<&str>,
+    ) -> Result<(&'a [u8], Section, T), nom::Err<Err>> {
+        let mut first_err = None;
+        for (arch, _) in self.detect(bytes, extension) {
+            let section = Section::new(arch);
+            match self.parsers[arch].from_bytes_and_meta(bytes, "", &section, self) {
+                Ok((bytes, _, t)) => return Ok((bytes, section, t)),
+                Err(e) => {
+                    first_err.get_or_insert(e.map(|e| e.locate("", bytes, arch)));
+                }
+            }
+        }
+        Err(first_err.unwrap_or(nom::Err::Error(Err::from(ErrorKind::NoParser))))
+    }
+}
+/// The names in `names`, sorted and without duplicates.
+fn arch_names<'k>(names: impl Iterator<Item = &'k String>) -> Vec<&'k str> {
+    let names: BTreeSet<&str> = names.map(String::as_str).collect();
+    names.into_iter().collect()
+}
+/// The parsers whose probe accepted the input, most confident first.
+fn rank<'k>(probes: impl Iterator<Item = (&'k String, Option<u32>)>) -> Vec<(&'k str, u32)> {
+    let mut ranked: Vec<(&str, u32)> = probes
+        .filter_map(|(arch, confidence)| Some((arch.as_str(), confidence?)))
+        .collect();
+    ranked.sort_by_key(|&(_, confidence)| core::cmp::Reverse(confidence));
+    ranked
+}
+/// A [`FileRegistry`] that owns its parsers and writers, so that it can be
+/// built at runtime and kept for as long as needed.
+///
+/// Parsing and writing go through a borrowed [`FileRegistry`] view, which is
+/// also the registry nested parsers are handed.
+pub struct OwnedFileRegistry<T, Err> {
+    pub parsers: BTreeMap<String, Arc<dyn FileParser<T, Err>>>,
+    pub writers: BTreeMap<String, Arc<dyn FileWriter<T, Err>>>,
+}
+impl<T, Err> Default for OwnedFileRegistry<T, Err> {
+    fn default() -> Self {
+        Self {
+            parsers: BTreeMap::new(),
+            writers: BTreeMap::new(),
+        }
+    }
+}
+impl<T, Err> Clone for OwnedFileRegistry<T, Err> {
+    fn clone(&self) -> Self {
+        Self {
+            parsers: self.parsers.clone(),
+            writers: self.writers.clone(),
+        }
+    }
+}
+impl<T, Err: From<ErrorKind>> OwnedFileRegistry<T, Err> {
+    pub fn register_parser(&mut self, extension: String, parser: Arc<dyn FileParser<T, Err>>) {
+        self.parsers.insert(extension, parser);
+    }
+    pub fn register_writer(&mut self, extension: String, writer: Arc<dyn FileWriter<T, Err>>) {
+        self.writers.insert(extension, writer);
+    }
+    /// Register a format that can both be parsed and written under one name.
+    pub fn register_format<P: FileParser<T, Err> + FileWriter<T, Err> + 'static>(
+        &mut self,
+        extension: String,
+        format: P,
+    ) {
+        let format = Arc::new(format);
+        self.parsers.insert(extension.clone(), format.clone());
+        self.writers.insert(extension, format);
+    }
+    /// A borrowed registry over the same parsers and writers.
+    pub fn view(&self) -> FileRegistry<'_, T, Err> {
+        FileRegistry {
+            parsers: self
+                .parsers
+                .iter()
+                .map(|(k, p)| (k.clone(), &**p as &dyn FileParser<T, Err>))
+                .collect(),
+            writers: self
+                .writers
+                .iter()
+                .map(|(k, w)| (k.clone(), &**w as &dyn FileWriter<T, Err>))
+                .collect(),
+        }
+    }
+    /// See [`FileRegistry::arches`].
+    pub fn arches(&self) -> Vec<&str> {
+        arch_names(self.parsers.keys().chain(self.writers.keys()))
+    }
+    /// See [`FileRegistry::describe`].
+    pub fn describe(&self, arch: &str) -> Option<String> {
+        self.parsers.get(arch)?.describe()
+    }
+    /// See [`FileRegistry::detect`].
+    pub fn detect(&self, bytes: &[u8], extension: Option<&str>) -> Vec<(&str, u32)> {
+        rank(
+            self.parsers
+                .iter()
+                .map(|(arch, p)| (arch, p.probe(bytes, extension))),
+        )
+    }
+    /// See [`FileRegistry::write_file`].
+    pub fn write_file(
+        &self,
+        sections: &[(Section, T)],
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+    ) -> Result<(), Err> {
+        self.view().write_file(sections, bytes, meta)
+    }
+}
+impl<T, Err: From<ErrorKind> + Locate> OwnedFileRegistry<T, Err> {
+    /// See [`FileRegistry::parse_file`].
+    pub fn parse_file<'a, 'b>(
+        &'a self,
+        bytes: &'a [u8],
+        meta: &'b str,
+    ) -> ParseResult<'a, 'b, Vec<T>, Err> {
+        self.view().parse_file(bytes, meta)
+    }
+    /// See [`FileRegistry::parse_sections`].
+    pub fn parse_sections<'a, 'b>(
+        &'a self,
+        bytes: &'a [u8],
+        meta: &'b str,
+    ) -> ParseResult<'a, 'b, Vec<(Section, T)>, Err> {
+        self.view().parse_sections(bytes, meta)
+    }
+    /// See [`FileRegistry::parse_detected`].
+    pub fn parse_detected<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        extension: Option<&str>,
+    ) -> Result<(&'a [u8], Section, T), nom::Err<Err>> {
+        self.view().parse_detected(bytes, extension)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
@@ -69,6 +394,27 @@ impl<'a, T, Err: From<ErrorKind>> FileRegistry<'a, T, Err> {
 pub enum ErrorKind {
     ArchIncomplete,
     NoParser,
+    NoWriter,
+    Format,
+    UnsupportedBase,
+    Checksum,
+    Truncated,
+    /// Conflicting bytes were placed at this address.
+    Overlap(u64),
+    /// A section header directive has a malformed value.
+    Directive,
+    /// The data does not start with the format's signature.
+    BadMagic,
+    /// The value handed to a writer is not of the writer's format.
+    WrongValue,
+    /// Internal links or tables in the data are inconsistent.
+    Corrupt,
+    /// A named item is not present.
+    NotFound,
+    /// There is no room left for the data.
+    Full,
+    /// The data is valid but uses a layout or feature that is not handled.
+    Unsupported,
     Nom(nom::error::ErrorKind),
 }
 impl Display for ErrorKind {
@@ -76,10 +422,27 @@ impl Display for ErrorKind {
         match self {
             ErrorKind::ArchIncomplete => write!(f, "Architecture information incomplete"),
             ErrorKind::NoParser => write!(f, "No parser found for given architecture"),
+            ErrorKind::NoWriter => write!(f, "No writer found for given architecture"),
+            ErrorKind::Format => write!(f, "Formatting error while writing"),
+            ErrorKind::UnsupportedBase => write!(f, "Unsupported numeric base"),
+            ErrorKind::Checksum => write!(f, "Checksum mismatch"),
+            ErrorKind::Truncated => write!(f, "Input truncated"),
+            ErrorKind::Overlap(address) => {
+                write!(f, "Conflicting bytes at address {:#x}", address)
+            }
+            ErrorKind::Directive => write!(f, "Malformed section directive"),
+            ErrorKind::BadMagic => write!(f, "Unrecognized file signature"),
+            ErrorKind::WrongValue => write!(f, "Value is not of the writer's format"),
+            ErrorKind::Corrupt => write!(f, "Inconsistent or corrupt data"),
+            ErrorKind::NotFound => write!(f, "Item not found"),
+            ErrorKind::Full => write!(f, "No space left"),
+            ErrorKind::Unsupported => write!(f, "Unsupported layout or feature"),
             ErrorKind::Nom(error_kind) => write!(f, "Nom error: {}", error_kind.description()),
         }
     }
 }
+impl Locate for ErrorKind {}
+impl<I> ContextError<I> for ErrorKind {}
 impl<I> ParseError<I> for ErrorKind {
     fn from_error_kind(_input: I, kind: nom::error::ErrorKind) -> Self {
         ErrorKind::Nom(kind)
@@ -89,4 +452,106 @@ impl<I> ParseError<I> for ErrorKind {
     }
 }
 
+pub mod diagnostic;
+pub mod formats;
+pub mod image;
 pub mod listing;
+pub mod section;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Bytes behind a magic prefix, which the probe scores `confidence`.
+    struct Magic(&'static [u8], u32);
+    impl FileParser<Vec<u8>, ErrorKind> for Magic {
+        fn from_bytes_and_meta<'a, 'b>(
+            &self,
+            bytes: &'a [u8],
+            meta: &'b str,
+            _section: &Section,
+            _registry: &FileRegistry<'_, Vec<u8>, ErrorKind>,
+        ) -> Result<(&'a [u8], &'b str, Vec<u8>), nom::Err<ErrorKind>> {
+            let data = bytes
+                .strip_prefix(self.0)
+                .ok_or(nom::Err::Error(ErrorKind::BadMagic))?;
+            Ok((&[], meta, data.to_vec()))
+        }
+        fn probe(&self, bytes: &[u8], _extension: Option<&str>) -> Option<u32> {
+            bytes.starts_with(self.0).then_some(self.1)
+        }
+        fn describe(&self) -> Option<String> {
+            Some(alloc::format!("{:?} magic", self.0))
+        }
+    }
+    impl FileWriter<Vec<u8>, ErrorKind> for Magic {
+        fn to_bytes_and_meta(
+            &self,
+            value: &Vec<u8>,
+            bytes: &mut Vec<u8>,
+            _meta: &mut String,
+            _section: &Section,
+            _registry: &FileRegistry<'_, Vec<u8>, ErrorKind>,
+        ) -> Result<(), ErrorKind> {
+            bytes.extend_from_slice(self.0);
+            bytes.extend_from_slice(value);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn detect_ranks_by_confidence() {
+        let formats = [
+            ("d", Magic(b"N", 50)),
+            ("a", Magic(b"NES", 10)),
+            ("b", Magic(b"NE", 50)),
+            ("c", Magic(b"X", 90)),
+        ];
+        let mut registry = FileRegistry::default();
+        for (arch, format) in &formats {
+            registry.register_parser((*arch).into(), format);
+        }
+        // ties keep name order; formats that rule the input out are left off
+        assert_eq!(
+            registry.detect(b"NES", None),
+            [("b", 50), ("d", 50), ("a", 10)]
+        );
+        assert_eq!(registry.detect(b"Q", None), []);
+        // the most confident parser is tried first
+        let (_, section, value) = registry.parse_detected(b"NES", None).unwrap();
+        assert_eq!((section.arch.as_str(), value), ("b", vec![b'S']));
+        assert_eq!(
+            registry.parse_detected(b"Q", None).unwrap_err(),
+            nom::Err::Error(ErrorKind::NoParser)
+        );
+    }
+    #[test]
+    fn owned_registry() {
+        let mut registry = OwnedFileRegistry::default();
+        registry.register_format("ab".into(), Magic(b"AB", 10));
+        registry.register_parser("a".into(), Arc::new(Magic(b"A", 5)));
+        assert_eq!(registry.arches(), ["a", "ab"]);
+        assert_eq!(registry.describe("ab").as_deref(), Some("[65, 66] magic"));
+        assert_eq!(registry.describe("none"), None);
+        assert_eq!(registry.detect(b"ABC", None), [("ab", 10), ("a", 5)]);
+
+        let sections = [(Section::new("ab"), vec![1, 2])];
+        let (mut bytes, mut meta) = (Vec::new(), String::new());
+        registry
+            .clone()
+            .write_file(&sections, &mut bytes, &mut meta)
+            .unwrap();
+        assert_eq!(bytes, b"AB\x01\x02");
+        let (rest, _, values) = registry.parse_file(&bytes, &meta).unwrap();
+        assert_eq!((rest, values), (&[][..], vec![vec![1, 2]]));
+        let (_, section, value) = registry.parse_detected(b"AQ", None).unwrap();
+        assert_eq!((section.arch.as_str(), value), ("a", vec![b'Q']));
+
+        let sections = [(Section::new("a"), vec![])];
+        assert_eq!(
+            registry.write_file(&sections, &mut bytes, &mut meta),
+            Err(ErrorKind::NoWriter)
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use crate::listing::core::{
-    ListingConfig, ListingEntry, format_grouped_number, grouped_value_to_bytes,
-    parse_dotted_groups, write_grouped_number,
+    EntryLayout, ListingConfig, ListingEntry, format_grouped_number, parse_dotted_groups,
+    parse_spaced_groups, write_grouped_number,
 };
-use crate::{ErrorKind, FileParser, FileRegistry};
-use alloc::{string::String, vec::Vec};
+use crate::listing::symbols::{Listing, SymbolCollector};
+use crate::section::Section;
+use crate::{ErrorKind, FileParser, FileRegistry, FileWriter};
+use alloc::{collections::btree_set::BTreeSet, string::String, vec::Vec};
 use core::fmt::Write;
 use nom::character::complete::{char as nom_char, not_line_ending, space0, space1};
-use nom::combinator::{all_consuming, map_res};
+use nom::combinator::{all_consuming, cut, map_res};
+use nom::error::context;
 use nom::multi::many0;
 use nom::sequence::{preceded, terminated, tuple};
 use nom::{IResult, Parser};
@@ -18,44 +21,134 @@ pub struct AsmParser {
 
 impl<Err> FileParser<Vec<ListingEntry>, Err> for AsmParser
 where
-    Err: From<ErrorKind> + for<'b>nom::error::ParseError<&'b str>,
+    Err: From<ErrorKind>
+        + for<'b> nom::error::ParseError<&'b str>
+        + for<'b> nom::error::ContextError<&'b str>,
 {
     fn from_bytes_and_meta<'a, 'b>(
         &self,
         bytes: &'a [u8],
         mut meta: &'b str,
+        _section: &Section,
         _registry: &FileRegistry<'_, Vec<ListingEntry>, Err>,
     ) -> Result<(&'a [u8], &'b str, Vec<ListingEntry>), nom::Err<Err>> {
         let entries;
         (meta, entries) = parse_asm_listing(meta, self.cfg)?;
         Ok((bytes, meta, entries))
     }
+    fn describe(&self) -> Option<String> {
+        Some(self.cfg.describe("asm listing"))
+    }
+}
+
+impl<Err> FileWriter<Vec<ListingEntry>, Err> for AsmParser
+where
+    Err: From<ErrorKind>,
+{
+    fn to_bytes_and_meta(
+        &self,
+        value: &Vec<ListingEntry>,
+        _bytes: &mut Vec<u8>,
+        meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, Vec<ListingEntry>, Err>,
+    ) -> Result<(), Err> {
+        write_asm_listing(meta, value, self.cfg).map_err(|_| Err::from(ErrorKind::Format))
+    }
+}
+
+impl<Err> FileParser<Listing, Err> for AsmParser
+where
+    Err: From<ErrorKind>
+        + for<'b> nom::error::ParseError<&'b str>
+        + for<'b> nom::error::ContextError<&'b str>,
+{
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        mut meta: &'b str,
+        _section: &Section,
+        _registry: &FileRegistry<'_, Listing, Err>,
+    ) -> Result<(&'a [u8], &'b str, Listing), nom::Err<Err>> {
+        let listing;
+        (meta, listing) = parse_asm_listing_with_symbols(meta, self.cfg)?;
+        Ok((bytes, meta, listing))
+    }
+    fn describe(&self) -> Option<String> {
+        Some(self.cfg.describe("asm listing with symbols"))
+    }
+}
+
+impl<Err> FileWriter<Listing, Err> for AsmParser
+where
+    Err: From<ErrorKind>,
+{
+    fn to_bytes_and_meta(
+        &self,
+        value: &Listing,
+        _bytes: &mut Vec<u8>,
+        meta: &mut String,
+        _section: &Section,
+        _registry: &FileRegistry<'_, Listing, Err>,
+    ) -> Result<(), Err> {
+        write_asm_listing_with_symbols(meta, value, self.cfg)
+            .map_err(|_| Err::from(ErrorKind::Format))
+    }
+}
+
+/// Width of the address column and the space after it, which is left
+/// blank on lines without an address.
+fn address_column(cfg: ListingConfig) -> usize {
+    cfg.addr_groups * (cfg.addr_group_width + 1)
 }
 
 /// A nom parser for a single line of an assembly listing.
-fn parse_asm_line<'a, E: nom::error::ParseError<&'a str>>(
+///
+/// Once an address and separator have been read the line is committed to,
+/// so a malformed entry is reported rather than ending the listing. A line
+/// whose address column is blank is an entry without an address.
+fn parse_asm_line<'a, E: nom::error::ParseError<&'a str> + nom::error::ContextError<&'a str>>(
     i: &'a str,
     cfg: ListingConfig,
 ) -> IResult<&'a str, ListingEntry, E> {
+    if let Some(column) = i.get(..address_column(cfg))
+        && column.bytes().all(|b| b == b' ')
+    {
+        let (i, text) = preceded(space0, not_line_ending).parse(&i[column.len()..])?;
+        return Ok((
+            i,
+            ListingEntry {
+                address: None,
+                bytes: Vec::new(),
+                text: String::from(text),
+            },
+        ));
+    }
     let mut addr_p = |i| parse_dotted_groups(i, cfg.base, cfg.addr_groups);
-    let mut entry_p = |i| parse_dotted_groups(i, cfg.base, cfg.entry_groups);
+    let entry_p = |i| match cfg.entry_layout {
+        EntryLayout::Fixed => {
+            let (i, entry_val) = parse_dotted_groups(i, cfg.base, cfg.entry_groups)?;
+            Ok((i, cfg.entry_to_bytes(entry_val)))
+        }
+        EntryLayout::Bytes => {
+            let (i, groups) = parse_spaced_groups(i, cfg, 0xff)?;
+            Ok((i, groups.into_iter().map(|b| b as u8).collect()))
+        }
+    };
 
-    let (i, (_, addr_val, _, entry_val, rest)) = (
+    let (i, (_, addr_val, _, bytes, rest)) = (
         space0,
         addr_p,
         space1,
-        entry_p,
+        cut(context("asm listing entry", entry_p)),
         preceded(space0, not_line_ending),
     )
         .parse(i)?;
 
-    let bytes =
-        grouped_value_to_bytes(entry_val, cfg.base, cfg.entry_group_width, cfg.entry_groups);
-
     Ok((
         i,
         ListingEntry {
-            address: addr_val as u64,
+            address: Some(addr_val as u64),
             bytes,
             text: String::from(rest),
         },
@@ -63,13 +156,45 @@ fn parse_asm_line<'a, E: nom::error::ParseError<&'a str>>(
 }
 
 /// Parse an assembly listing text into `ListingEntry`s according to `cfg`.
-pub fn parse_asm_listing<'a, E: nom::error::ParseError<&'a str>>(
+pub fn parse_asm_listing<
+    'a,
+    E: nom::error::ParseError<&'a str> + nom::error::ContextError<&'a str>,
+>(
     text: &'a str,
     cfg: ListingConfig,
 ) -> IResult<&'a str, Vec<ListingEntry>, E> {
     many0(terminated(|i| parse_asm_line(i, cfg), nom_char('\n'))).parse(text)
 }
 
+/// Parse an assembly listing text along with its symbols: `name:` labels,
+/// either on a line of their own or leading an entry's text, and
+/// `NAME = value` or `NAME EQU value` definitions.
+pub fn parse_asm_listing_with_symbols<
+    'a,
+    E: nom::error::ParseError<&'a str> + nom::error::ContextError<&'a str>,
+>(
+    mut text: &'a str,
+    cfg: ListingConfig,
+) -> IResult<&'a str, Listing, E> {
+    let mut entries = Vec::new();
+    let mut symbols = SymbolCollector::default();
+    while let Some((line, rest)) = text.split_once('\n') {
+        if symbols.line(line) {
+            text = rest;
+            continue;
+        }
+        let mut entry;
+        (text, entry) = match terminated(|i| parse_asm_line(i, cfg), nom_char('\n')).parse(text) {
+            Err(nom::Err::Error(_)) => break,
+            res => res?,
+        };
+        symbols.entry(&mut entry);
+        entries.push(entry);
+    }
+    let symbols = symbols.finish(&entries);
+    Ok((text, Listing { entries, symbols }))
+}
+
 /// Print listing entries as assembly listing lines according to `cfg` into the provided writer.
 pub fn write_asm_listing<W: Write>(
     w: &mut W,
@@ -77,20 +202,34 @@ pub fn write_asm_listing<W: Write>(
     cfg: ListingConfig,
 ) -> core::fmt::Result {
     for e in entries {
+        let Some(address) = e.address else {
+            write!(w, "{:1$}", "", address_column(cfg))?;
+            w.write_str(&e.text)?;
+            w.write_char('\n')?;
+            continue;
+        };
         write_grouped_number(
             w,
-            e.address as u128,
+            address as u128,
             cfg,
             cfg.addr_groups,
             cfg.addr_group_width,
         )?;
         w.write_char(' ')?;
-        // reconstruct entry numeric from bytes (big-endian)
-        let mut val: u128 = 0;
-        for &b in &e.bytes {
-            val = (val << 8) | (b as u128);
+        match cfg.entry_layout {
+            EntryLayout::Fixed => {
+                let val = cfg.entry_from_bytes(&e.bytes);
+                write_grouped_number(w, val, cfg, cfg.entry_groups, cfg.entry_group_width)?;
+            }
+            EntryLayout::Bytes => {
+                for (i, &b) in e.bytes.iter().enumerate() {
+                    if i > 0 {
+                        w.write_char(' ')?;
+                    }
+                    write_grouped_number(w, b as u128, cfg, 1, cfg.entry_group_width)?;
+                }
+            }
         }
-        write_grouped_number(w, val, cfg, cfg.entry_groups, cfg.entry_group_width)?;
         if !e.text.is_empty() {
             w.write_char(' ')?;
             w.write_str(&e.text)?;
@@ -106,3 +245,86 @@ pub fn print_asm_listing(entries: &[ListingEntry], cfg: ListingConfig) -> String
     write_asm_listing(&mut s, entries, cfg).unwrap();
     s
 }
+
+/// Print a listing with its symbols according to `cfg` into the provided
+/// writer: definitions first, then each label on its own line ahead of the
+/// first entry at its address.
+pub fn write_asm_listing_with_symbols<W: Write>(
+    w: &mut W,
+    listing: &Listing,
+    cfg: ListingConfig,
+) -> core::fmt::Result {
+    listing
+        .symbols
+        .write_definitions(w, &listing.entries, cfg.base)?;
+    let mut labelled = BTreeSet::new();
+    for e in &listing.entries {
+        if let Some(address) = e.address
+            && labelled.insert(address)
+        {
+            listing.symbols.write_labels(w, address)?;
+        }
+        write_asm_listing(w, core::slice::from_ref(e), cfg)?;
+    }
+    Ok(())
+}
+
+/// Print a listing with its symbols according to `cfg`.
+pub fn print_asm_listing_with_symbols(listing: &Listing, cfg: ListingConfig) -> String {
+    let mut s = String::new();
+    write_asm_listing_with_symbols(&mut s, listing, cfg).unwrap();
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+
+    fn entry(address: Option<u64>, bytes: &[u8], text: &str) -> ListingEntry {
+        ListingEntry {
+            address,
+            bytes: bytes.into(),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn unaddressed_round_trip() {
+        let cfg = ListingConfig::new_hex(1, 4, 1, 2);
+        let entries = [
+            entry(None, &[], "; header"),
+            entry(Some(0), &[0xa9], "LDA"),
+            entry(None, &[], ""),
+            entry(Some(1), &[0x01], "#1"),
+        ];
+        let text = print_asm_listing(&entries, cfg);
+        assert_eq!(text, "     ; header\n0000 a9 LDA\n     \n0001 01 #1\n");
+        let (rest, back) = parse_asm_listing::<Diagnostic>(&text, cfg).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(back.len(), entries.len());
+        for (a, b) in back.iter().zip(&entries) {
+            assert_eq!(
+                (a.address, &a.bytes, &a.text),
+                (b.address, &b.bytes, &b.text)
+            );
+        }
+    }
+    #[test]
+    fn variable_length_round_trip() {
+        let cfg = ListingConfig::new_hex(1, 4, 3, 2).with_entry_layout(EntryLayout::Bytes);
+        let text = "c000 a9 05 LDA #$05\nc002 60 RTS\nc003 6d 00 c0 ADC $c000\n";
+        let (rest, entries) = parse_asm_listing::<Diagnostic>(text, cfg).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(entries[2].bytes, [0x6d, 0, 0xc0]);
+        assert_eq!(print_asm_listing(&entries, cfg), text);
+    }
+    #[test]
+    fn malformed_entry() {
+        let cfg = ListingConfig::new_hex(1, 4, 1, 2);
+        assert!(matches!(
+            parse_asm_listing::<Diagnostic>("0000 a9 LDA\nc000 zz\n", cfg),
+            Err(nom::Err::Failure(_))
+        ));
+    }
+}
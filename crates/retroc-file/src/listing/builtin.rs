@@ -0,0 +1,113 @@
+use crate::listing::asm::AsmParser;
+use crate::listing::core::{EntryLayout, ListingConfig};
+use crate::listing::detached::DetachedParser;
+use crate::{ByteMeta, ErrorKind, FileParser, FileRegistry, FileWriter};
+
+// Built-in formats are named `<kind>-<base>-<address bits>`. Asm listings
+// take up to eight space-separated bytes per line; detached listings take
+// one byte per line.
+
+const fn asm(cfg: ListingConfig) -> AsmParser {
+    AsmParser {
+        cfg: cfg.with_entry_layout(EntryLayout::Bytes),
+    }
+}
+
+const fn detached(cfg: ListingConfig) -> ByteMeta<DetachedParser> {
+    ByteMeta(DetachedParser { cfg })
+}
+
+pub static ASM_HEX_16: AsmParser = asm(ListingConfig::new_hex(1, 4, 8, 2));
+pub static ASM_HEX_24: AsmParser = asm(ListingConfig::new_hex(1, 6, 8, 2));
+pub static ASM_HEX_32: AsmParser = asm(ListingConfig::new_hex(1, 8, 8, 2));
+pub static ASM_OCTAL_16: AsmParser = asm(ListingConfig::new_octal(1, 6, 8, 3));
+pub static DETACHED_HEX_16: ByteMeta<DetachedParser> = detached(ListingConfig::new_hex(1, 4, 1, 2));
+pub static DETACHED_HEX_24: ByteMeta<DetachedParser> = detached(ListingConfig::new_hex(1, 6, 1, 2));
+pub static DETACHED_HEX_32: ByteMeta<DetachedParser> = detached(ListingConfig::new_hex(1, 8, 1, 2));
+pub static DETACHED_OCTAL_16: ByteMeta<DetachedParser> =
+    detached(ListingConfig::new_octal(1, 6, 1, 3));
+
+/// The built-in asm listing formats by name.
+pub static ASM_BUILTINS: [(&str, &AsmParser); 4] = [
+    ("asm-hex-16", &ASM_HEX_16),
+    ("asm-hex-24", &ASM_HEX_24),
+    ("asm-hex-32", &ASM_HEX_32),
+    ("asm-octal-16", &ASM_OCTAL_16),
+];
+
+/// The built-in detached listing formats by name.
+pub static DETACHED_BUILTINS: [(&str, &ByteMeta<DetachedParser>); 4] = [
+    ("detached-hex-16", &DETACHED_HEX_16),
+    ("detached-hex-24", &DETACHED_HEX_24),
+    ("detached-hex-32", &DETACHED_HEX_32),
+    ("detached-octal-16", &DETACHED_OCTAL_16),
+];
+
+/// Register every built-in listing format with `registry`, for listings of
+/// either `Vec<ListingEntry>` or [`Listing`](super::symbols::Listing).
+pub fn register_builtins<T, Err>(registry: &mut FileRegistry<'_, T, Err>)
+where
+    Err: From<ErrorKind>,
+    AsmParser: FileParser<T, Err> + FileWriter<T, Err>,
+    ByteMeta<DetachedParser>: FileParser<T, Err> + FileWriter<T, Err>,
+{
+    for (name, format) in ASM_BUILTINS {
+        registry.register_format(name.into(), format);
+    }
+    for (name, format) in DETACHED_BUILTINS {
+        registry.register_format(name.into(), format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listing::core::ListingEntry;
+    use alloc::{string::String, vec, vec::Vec};
+
+    fn registry() -> FileRegistry<'static, Vec<ListingEntry>, ErrorKind> {
+        let mut registry = FileRegistry::default();
+        register_builtins(&mut registry);
+        registry
+    }
+
+    #[test]
+    fn detached_then_asm_round_trip() {
+        let registry = registry();
+        let meta = "arch detached-hex-16\n; reset\n0000 nop\n0001 rts\narch asm-hex-16\nc000 a9 01 lda #1\n";
+        let bytes = [0xea, 0x60];
+        let (rest, left, sections) = registry.parse_sections(&bytes, meta).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(left, "");
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].1.len(), 3);
+        assert_eq!(sections[0].1[2].bytes, [0x60]);
+        assert_eq!(sections[1].1[0].bytes, [0xa9, 0x01]);
+        let (mut b, mut m) = (Vec::new(), String::new());
+        registry.write_file(&sections, &mut b, &mut m).unwrap();
+        assert_eq!((b.as_slice(), m.as_str()), (&bytes[..], meta));
+    }
+    #[test]
+    fn detached_lines_shaped_like_directives() {
+        let registry = registry();
+        let meta = "arch detached-hex-16\nreset vector\n0000 nop\nc000 rts\n.bank 1\narch asm-hex-16\nc000 a9 01 lda #1\n";
+        let mut bytes = vec![0; 0xc001];
+        bytes[0xc000] = 0x60;
+        let (_, left, sections) = registry.parse_sections(&bytes, meta).unwrap();
+        assert_eq!(left, "");
+        let texts: Vec<&str> = sections[0].1.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, ["reset vector", "nop", "rts"]);
+        assert_eq!(sections[0].1[2].bytes, [0x60]);
+        assert_eq!(sections[1].0.bank, Some(1));
+        assert!(sections[1].0.attrs.is_empty());
+    }
+    #[test]
+    fn detached_address_past_end() {
+        let registry = registry();
+        let meta = "arch detached-hex-16\n0000 nop\n0005 rts\n";
+        assert_eq!(
+            registry.parse_file(&[0xea], meta).unwrap_err(),
+            nom::Err::Error(ErrorKind::Truncated)
+        );
+    }
+}
@@ -1,15 +1,88 @@
+use crate::ErrorKind;
 use alloc::{string::String, vec::Vec};
-use nom::bytes::take_while1;
 use core::fmt::{self, Display, Write};
-use nom::character::complete::{char as nom_char, hex_digit1};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while, take_while_m_n, take_while1};
+use nom::character::complete::{char as nom_char, line_ending, one_of, space1};
+use nom::combinator::{eof, map_opt, peek, recognize};
 use nom::multi::separated_list1;
+use nom::sequence::{preceded, terminated};
 use nom::{IResult, Parser};
 
+/// A numeric base listings can be written in.
+///
+/// Constructed from a raw radix with `Base::try_from`, which rejects any
+/// base that is not supported instead of misreading its digits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Base {
+    Binary,
+    Octal,
+    Decimal,
+    Hex,
+}
+
+impl Base {
+    pub const fn radix(self) -> u32 {
+        match self {
+            Base::Binary => 2,
+            Base::Octal => 8,
+            Base::Decimal => 10,
+            Base::Hex => 16,
+        }
+    }
+
+    /// Whether `c` is a digit in this base.
+    pub fn is_digit(self, c: char) -> bool {
+        c.is_digit(self.radix())
+    }
+
+    /// Number of bits needed to hold any number of `digits` digits.
+    pub fn bits_for_digits(self, digits: usize) -> usize {
+        match self {
+            Base::Binary => digits,
+            Base::Octal => 3 * digits,
+            Base::Hex => 4 * digits,
+            // smallest bit count whose range covers 10^digits values
+            Base::Decimal => {
+                let mut bits = 0;
+                let mut range: u128 = 1;
+                for _ in 0..digits {
+                    range = range.saturating_mul(10);
+                }
+                while bits < 128 && (1u128 << bits) < range {
+                    bits += 1;
+                }
+                bits
+            }
+        }
+    }
+
+    /// Append a group of `digits` digits to `value`.
+    fn shift_in(self, value: u128, digits: usize, part: u128) -> u128 {
+        value
+            .wrapping_mul((self.radix() as u128).wrapping_pow(digits as u32))
+            .wrapping_add(part)
+    }
+}
+
+impl TryFrom<u8> for Base {
+    type Error = ErrorKind;
+    fn try_from(radix: u8) -> Result<Self, ErrorKind> {
+        match radix {
+            2 => Ok(Base::Binary),
+            8 => Ok(Base::Octal),
+            10 => Ok(Base::Decimal),
+            16 => Ok(Base::Hex),
+            _ => Err(ErrorKind::UnsupportedBase),
+        }
+    }
+}
+
 /// Configuration for formatting/parsing listings.
 #[derive(Clone, Copy, Debug)]
 pub struct ListingConfig {
-    /// Numeric base: 8 or 16 are supported.
-    pub base: u8,
+    /// Numeric base of addresses and entries.
+    pub base: Base,
     /// Number of groups in the address (e.g. 2 for `ffff.ffff`).
     pub addr_groups: usize,
     /// Width (digits) of each address group (e.g. 4 for hex `ffff`, 3 for octal `777`).
@@ -18,106 +91,308 @@ pub struct ListingConfig {
     pub entry_groups: usize,
     /// Width (digits) of each entry group.
     pub entry_group_width: usize,
+    /// How the entry groups are laid out on a line.
+    pub entry_layout: EntryLayout,
+    /// Order of the bytes within each word of an entry.
+    pub byte_order: ByteOrder,
+    /// Size of a word in bits; entries are whole words. Must be a multiple of 8
+    /// for byte entries, while [`WordEntry`](super::word::WordEntry) takes any
+    /// size up to 64.
+    pub word_bits: usize,
+}
+
+/// Byte order within a word.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+/// How the entry field of a listing line is laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryLayout {
+    /// Exactly `entry_groups` dotted groups forming a single value, as in `0000.0000`.
+    Fixed,
+    /// One to `entry_groups` space-separated groups of one byte each, as in
+    /// `c000 a9 05 LDA #$05` for machines with variable-length instructions.
+    Bytes,
 }
 
 impl ListingConfig {
-    pub fn new_hex(
+    /// A configuration in `base`, which must be 2, 8, 10 or 16.
+    pub fn new(
+        base: u8,
+        addr_groups: usize,
+        addr_group_width: usize,
+        entry_groups: usize,
+        entry_group_width: usize,
+    ) -> Result<Self, ErrorKind> {
+        Ok(Self {
+            base: Base::try_from(base)?,
+            addr_groups,
+            addr_group_width,
+            entry_groups,
+            entry_group_width,
+            entry_layout: EntryLayout::Fixed,
+            byte_order: ByteOrder::Big,
+            word_bits: 8,
+        })
+    }
+    pub const fn new_hex(
+        addr_groups: usize,
+        addr_group_width: usize,
+        entry_groups: usize,
+        entry_group_width: usize,
+    ) -> Self {
+        Self {
+            base: Base::Hex,
+            addr_groups,
+            addr_group_width,
+            entry_groups,
+            entry_group_width,
+            entry_layout: EntryLayout::Fixed,
+            byte_order: ByteOrder::Big,
+            word_bits: 8,
+        }
+    }
+    pub const fn new_octal(
         addr_groups: usize,
         addr_group_width: usize,
         entry_groups: usize,
         entry_group_width: usize,
     ) -> Self {
         Self {
-            base: 16,
+            base: Base::Octal,
             addr_groups,
             addr_group_width,
             entry_groups,
             entry_group_width,
+            entry_layout: EntryLayout::Fixed,
+            byte_order: ByteOrder::Big,
+            word_bits: 8,
         }
     }
-    pub fn new_octal(
+    pub const fn new_binary(
         addr_groups: usize,
         addr_group_width: usize,
         entry_groups: usize,
         entry_group_width: usize,
     ) -> Self {
         Self {
-            base: 8,
+            base: Base::Binary,
             addr_groups,
             addr_group_width,
             entry_groups,
             entry_group_width,
+            entry_layout: EntryLayout::Fixed,
+            byte_order: ByteOrder::Big,
+            word_bits: 8,
         }
     }
+    pub const fn new_decimal(
+        addr_groups: usize,
+        addr_group_width: usize,
+        entry_groups: usize,
+        entry_group_width: usize,
+    ) -> Self {
+        Self {
+            base: Base::Decimal,
+            addr_groups,
+            addr_group_width,
+            entry_groups,
+            entry_group_width,
+            entry_layout: EntryLayout::Fixed,
+            byte_order: ByteOrder::Big,
+            word_bits: 8,
+        }
+    }
+    /// Use `layout` for the entry field.
+    pub const fn with_entry_layout(mut self, layout: EntryLayout) -> Self {
+        self.entry_layout = layout;
+        self
+    }
 
-    fn group_bits(&self) -> usize {
-        // number of bits per group (base 16 => 4 bits/digit, base 8 => 3 bits/digit)
-        let bits_per_digit = match self.base {
-            16 => 4,
-            8 => 3,
-            _ => 4,
+    /// Use `order` for the bytes within each word.
+    pub const fn with_byte_order(mut self, order: ByteOrder) -> Self {
+        self.byte_order = order;
+        self
+    }
+    /// Use words of `bits` bits. Fails with [`ErrorKind::Format`] unless
+    /// `bits` is between 1 and 64.
+    pub const fn with_word_bits(mut self, bits: usize) -> Result<Self, ErrorKind> {
+        if bits == 0 || bits > 64 {
+            return Err(ErrorKind::Format);
+        }
+        self.word_bits = bits;
+        Ok(self)
+    }
+
+    /// Describe a listing of the given `kind` in this configuration, e.g.
+    /// `asm listing, hex, 4-digit addresses`.
+    pub fn describe(&self, kind: &str) -> String {
+        let base = match self.base {
+            Base::Binary => "binary",
+            Base::Octal => "octal",
+            Base::Decimal => "decimal",
+            Base::Hex => "hex",
         };
-        bits_per_digit * self.entry_group_width
+        let mut s = alloc::format!(
+            "{}, {}, {}-digit addresses",
+            kind,
+            base,
+            self.addr_groups * self.addr_group_width
+        );
+        if self.word_bits != 8 {
+            write!(s, ", {}-bit words", self.word_bits).unwrap();
+        }
+        s
+    }
+
+    fn word_bytes(&self) -> usize {
+        (self.word_bits / 8).max(1)
+    }
+
+    /// Number of bytes a fixed-layout entry occupies: enough to hold any
+    /// value of its digits, rounded up to whole words.
+    pub fn entry_bytes(&self) -> usize {
+        let bytes = self
+            .base
+            .bits_for_digits(self.entry_group_width * self.entry_groups)
+            .div_ceil(8);
+        bytes.next_multiple_of(self.word_bytes())
+    }
+
+    /// Convert an entry value into bytes in memory order: words are in
+    /// ascending address order with bytes within each in `byte_order`.
+    pub fn entry_to_bytes(&self, value: u128) -> Vec<u8> {
+        grouped_value_to_bytes(
+            value,
+            self.base,
+            self.entry_group_width,
+            self.entry_groups,
+            self.byte_order,
+            self.word_bytes(),
+        )
+    }
+
+    /// Convert bytes in memory order back into an entry value, the inverse
+    /// of [`ListingConfig::entry_to_bytes`].
+    pub fn entry_from_bytes(&self, bytes: &[u8]) -> u128 {
+        let mut value: u128 = 0;
+        for w in bytes.chunks(self.word_bytes()) {
+            let mut word = |b: &u8| value = (value << 8) | (*b as u128);
+            match self.byte_order {
+                ByteOrder::Big => w.iter().for_each(&mut word),
+                ByteOrder::Little => w.iter().rev().for_each(&mut word),
+            }
+        }
+        value
+    }
+
+    fn group_bits(&self) -> usize {
+        self.base.bits_for_digits(self.entry_group_width)
     }
 }
 
 /// A nom-style parser for dotted groups into a numeric value.
-pub fn parse_dotted_groups<'a,Err: nom::error::ParseError<&'a str>>(
-    i: &'a str,
-    base: u8,
+pub fn parse_dotted_groups<'a, Err: nom::error::ParseError<&'a str>>(
+    start: &'a str,
+    base: Base,
     expected_groups: usize,
 ) -> IResult<&'a str, u128, Err> {
-    let (i, groups) = if base == 16 {
-        separated_list1(nom_char('.'), hex_digit1).parse(i)?
-    } else if base == 8 {
-        separated_list1(nom_char('.'), take_while1(|c: char| c >= '0' && c <= '7')).parse(i)?
-    } else {
-        return Err(nom::Err::Error(Err::from_error_kind(
-            i,
-            nom::error::ErrorKind::Tag,
-        )));
-    };
+    let i = start;
+    let (i, groups) =
+        separated_list1(nom_char('.'), take_while1(|c: char| base.is_digit(c))).parse(i)?;
 
     if groups.len() != expected_groups {
+        // report the number as a whole rather than what follows it
         return Err(nom::Err::Error(Err::from_error_kind(
-            i,
+            start,
             nom::error::ErrorKind::Verify,
         )));
     }
 
     let mut value: u128 = 0;
     for part in groups {
-        let part_val = u128::from_str_radix(part, base as u32).map_err(|_| {
+        let part_val = u128::from_str_radix(part, base.radix()).map_err(|_| {
             nom::Err::Error(Err::from_error_kind(part, nom::error::ErrorKind::Digit))
         })?;
-        let bits = match base {
-            16 => 4 * part.len(),
-            8 => 3 * part.len(),
-            _ => 4 * part.len(),
-        };
-        value = (value << bits) | part_val;
+        value = base.shift_in(value, part.len(), part_val);
     }
     Ok((i, value))
 }
 
+/// A nom parser for one to `cfg.entry_groups` groups separated by single
+/// spaces, each exactly `cfg.entry_group_width` digits wide and at most `max`.
+///
+/// A group must be followed by whitespace or the end of the line, so text
+/// such as `ADC` is not mistaken for a group; a mnemonic that is itself a
+/// valid group (`DB 05`) is still read as one unless the group limit is hit.
+pub fn parse_spaced_groups<'a, E: nom::error::ParseError<&'a str>>(
+    i: &'a str,
+    cfg: ListingConfig,
+    max: u128,
+) -> IResult<&'a str, Vec<u128>, E> {
+    let width = cfg.entry_group_width;
+    let mut groups = Vec::new();
+    let mut rest = i;
+    while groups.len() < cfg.entry_groups {
+        let start = if groups.is_empty() {
+            rest
+        } else {
+            match rest.strip_prefix(' ') {
+                Some(r) => r,
+                None => break,
+            }
+        };
+        let res: IResult<&str, &str, E> = terminated(
+            take_while_m_n(width, width, |c: char| cfg.base.is_digit(c)),
+            peek(alt((space1, line_ending, eof))),
+        )
+        .parse(start);
+        let (r, digits) = match res {
+            Ok(x) => x,
+            Err(e) if groups.is_empty() => return Err(e),
+            Err(_) => break,
+        };
+        match u128::from_str_radix(digits, cfg.base.radix()) {
+            Ok(v) if v <= max => groups.push(v),
+            _ => {
+                return Err(nom::Err::Error(E::from_error_kind(
+                    start,
+                    nom::error::ErrorKind::Verify,
+                )));
+            }
+        }
+        rest = r;
+    }
+    Ok((rest, groups))
+}
+
 /// A single listing entry: address, raw bytes and text (mnemonic/comment)
+///
+/// Lines that carry no address, such as detached comment lines, have an
+/// `address` of `None`.
 #[derive(Clone, Debug)]
 pub struct ListingEntry {
-    pub address: u64,
+    pub address: Option<u64>,
     pub bytes: Vec<u8>,
     pub text: String,
 }
 
 impl Display for ListingEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:08x}: {}", self.address, self.text)
+        match self.address {
+            Some(address) => write!(f, "{:08x}: {}", address, self.text),
+            None => f.write_str(&self.text),
+        }
     }
 }
 
 /// Helpers used by parsers/printers: parse a dotted group string into a numeric value.
 pub fn parse_grouped_number(
     s: &str,
-    base: u8,
+    base: Base,
     expected_groups: Option<usize>,
 ) -> Result<u128, &'static str> {
     let parts: Vec<&str> = s.split('.').collect();
@@ -129,14 +404,9 @@ pub fn parse_grouped_number(
     let mut value: u128 = 0;
     for part in parts {
         let part_val =
-            u128::from_str_radix(part, base as u32).map_err(|_| "invalid group digits")?;
-        // shift previous by sufficient bits to append next group
-        let bits = match base {
-            16 => 4 * part.len(),
-            8 => 3 * part.len(),
-            _ => 4 * part.len(),
-        };
-        value = (value << bits) | part_val;
+            u128::from_str_radix(part, base.radix()).map_err(|_| "invalid group digits")?;
+        // scale previous groups to append the next one
+        value = base.shift_in(value, part.len(), part_val);
     }
     Ok(value)
 }
@@ -149,23 +419,19 @@ pub fn write_grouped_number<W: Write>(
     groups: usize,
     group_width: usize,
 ) -> fmt::Result {
-    let base = cfg.base as u128;
+    let scale = (cfg.base.radix() as u128).saturating_pow(group_width as u32);
     let mut parts: Vec<String> = Vec::new();
     for _ in 0..groups {
-        let mask = base.pow(group_width as u32) - 1;
-        let part = (value & mask) as u128;
+        let part = value % scale;
         let mut s = String::new();
-        if cfg.base == 16 {
-            write!(s, "{:0width$x}", part, width = group_width).unwrap();
-        } else {
-            write!(s, "{:0width$o}", part, width = group_width).unwrap();
+        match cfg.base {
+            Base::Binary => write!(s, "{:0width$b}", part, width = group_width).unwrap(),
+            Base::Octal => write!(s, "{:0width$o}", part, width = group_width).unwrap(),
+            Base::Decimal => write!(s, "{:0width$}", part, width = group_width).unwrap(),
+            Base::Hex => write!(s, "{:0width$x}", part, width = group_width).unwrap(),
         };
         parts.push(s);
-        value >>= match cfg.base {
-            16 => 4 * group_width,
-            8 => 3 * group_width,
-            _ => 4 * group_width,
-        };
+        value /= scale;
     }
     parts.reverse();
     for (i, p) in parts.iter().enumerate() {
@@ -189,30 +455,118 @@ pub fn format_grouped_number(
     s
 }
 
-/// Convert a grouped numeric value into bytes (big-endian) where each group contributes group_bits bits.
+/// Convert a grouped numeric value into bytes in memory order, as many as it
+/// takes to hold any value of `groups` groups of `group_width` digits rounded
+/// up to whole words of `word_bytes` bytes. Words are in ascending address
+/// order with bytes within each in `order`.
 pub fn grouped_value_to_bytes(
-    mut value: u128,
-    base: u8,
+    value: u128,
+    base: Base,
     group_width: usize,
     groups: usize,
+    order: ByteOrder,
+    word_bytes: usize,
 ) -> Vec<u8> {
-    let mut out = Vec::new();
-    let group_bits = match base {
-        16 => 4 * group_width,
-        8 => 3 * group_width,
-        _ => 4 * group_width,
-    };
-    let mut total_bits = group_bits * groups;
-    if total_bits == 0 {
-        return out;
-    }
-    // round up to bytes
-    let total_bytes = (total_bits + 7) / 8;
-    // produce big-endian bytes
-    for i in (0..total_bytes).rev() {
-        let shift = i * 8;
-        let b = ((value >> shift) & 0xff) as u8;
-        out.push(b);
+    let n = base
+        .bits_for_digits(group_width * groups)
+        .div_ceil(8)
+        .next_multiple_of(word_bytes);
+    let mut out: Vec<u8> = (0..n)
+        .rev()
+        .map(|i| value.checked_shr(8 * i as u32).unwrap_or(0) as u8)
+        .collect();
+    if order == ByteOrder::Little {
+        for w in out.chunks_mut(word_bytes) {
+            w.reverse();
+        }
     }
     out
 }
+
+/// A nom parser for a numeric literal as written in assembler source:
+/// `$ff`, `0xff` or `0ffh` in hex, `%101` or `0b101` in binary, `@17` or
+/// `0o17` in octal, and plain decimal otherwise.
+pub fn parse_literal<'a, E: nom::error::ParseError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, u64, E> {
+    let digits = |base: Base| {
+        map_opt(
+            take_while1(move |c: char| base.is_digit(c)),
+            move |s: &str| u64::from_str_radix(s, base.radix()).ok(),
+        )
+    };
+    let hex_suffix = map_opt(
+        terminated(
+            recognize((
+                take_while_m_n(1, 1, |c: char| c.is_ascii_digit()),
+                take_while(|c: char| c.is_ascii_hexdigit()),
+            )),
+            one_of("hH"),
+        ),
+        |s: &str| u64::from_str_radix(s, 16).ok(),
+    );
+    alt((
+        hex_suffix,
+        preceded(alt((tag("$"), tag("0x"), tag("0X"))), digits(Base::Hex)),
+        preceded(alt((tag("%"), tag("0b"), tag("0B"))), digits(Base::Binary)),
+        preceded(alt((tag("@"), tag("0o"), tag("0O"))), digits(Base::Octal)),
+        digits(Base::Decimal),
+    ))
+    .parse(i)
+}
+
+/// Write `value` as a literal in `base` that [`parse_literal`] reads back.
+pub fn write_literal<W: Write>(w: &mut W, value: u64, base: Base) -> fmt::Result {
+    match base {
+        Base::Binary => write!(w, "%{:b}", value),
+        Base::Octal => write!(w, "@{:o}", value),
+        Base::Decimal => write!(w, "{}", value),
+        Base::Hex => write!(w, "${:x}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_from_radix() {
+        for base in [Base::Binary, Base::Octal, Base::Decimal, Base::Hex] {
+            assert_eq!(Base::try_from(base.radix() as u8), Ok(base));
+        }
+        for radix in [0, 1, 3, 7, 9, 12, 15, 17, 36, 255] {
+            assert_eq!(Base::try_from(radix), Err(ErrorKind::UnsupportedBase));
+        }
+        assert_eq!(
+            ListingConfig::new(12, 1, 4, 1, 2).unwrap_err(),
+            ErrorKind::UnsupportedBase
+        );
+        assert_eq!(ListingConfig::new(8, 1, 6, 1, 3).unwrap().base, Base::Octal);
+    }
+    #[test]
+    fn grouped_value_byte_order() {
+        let value = 0x1234_5678;
+        let big = grouped_value_to_bytes(value, Base::Hex, 4, 2, ByteOrder::Big, 2);
+        assert_eq!(big, [0x12, 0x34, 0x56, 0x78]);
+        let little = grouped_value_to_bytes(value, Base::Hex, 4, 2, ByteOrder::Little, 2);
+        assert_eq!(little, [0x34, 0x12, 0x78, 0x56]);
+        // Six octal digits are 18 bits, rounded up to two 16-bit words.
+        let octal = grouped_value_to_bytes(0o123456, Base::Octal, 6, 1, ByteOrder::Little, 2);
+        assert_eq!(octal, [0x00, 0x00, 0x2e, 0xa7]);
+
+        let cfg = ListingConfig::new_hex(1, 4, 2, 4)
+            .with_byte_order(ByteOrder::Little)
+            .with_word_bits(16)
+            .unwrap();
+        assert_eq!(cfg.entry_to_bytes(value), little);
+        assert_eq!(cfg.entry_from_bytes(&little), value);
+    }
+    #[test]
+    fn word_bits_range() {
+        let cfg = ListingConfig::new_hex(1, 4, 1, 2);
+        assert_eq!(cfg.with_word_bits(0).unwrap_err(), ErrorKind::Format);
+        assert_eq!(cfg.with_word_bits(65).unwrap_err(), ErrorKind::Format);
+        assert_eq!(cfg.with_word_bits(1).unwrap().word_bits, 1);
+        assert_eq!(cfg.with_word_bits(64).unwrap().word_bits, 64);
+    }
+}
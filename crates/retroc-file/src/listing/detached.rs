@@ -1,9 +1,11 @@
+use crate::image::MemoryImage;
 use crate::listing::core::{
     ListingConfig, ListingEntry, format_grouped_number, grouped_value_to_bytes,
     parse_dotted_groups, write_grouped_number,
 };
-use crate::{ByteMetaParser, ErrorKind};
-use alloc::{string::String, vec::Vec};
+use crate::listing::symbols::{Listing, SymbolCollector};
+use crate::{ByteMetaParser, ByteMetaWriter, ErrorKind, section};
+use alloc::{collections::btree_set::BTreeSet, string::String, vec::Vec};
 use core::fmt::Write;
 use nom::bytes::take_while1;
 use nom::character::complete::{char as nom_char, not_line_ending, space0, space1};
@@ -30,6 +32,53 @@ where
     ) -> Result<(&'a [u8], &'b str, Vec<ListingEntry>), nom::Err<Err>> {
         Ok(parse_detached_listing(bytes, meta, self.cfg)?)
     }
+    fn describe(&self) -> Option<String> {
+        Some(self.cfg.describe("detached listing"))
+    }
+}
+
+impl<Err> ByteMetaParser<Listing, Err> for DetachedParser
+where
+    Err: From<ErrorKind>
+        + for<'a> nom::error::ParseError<&'a str>
+        + for<'b> nom::error::ParseError<&'b [u8]>,
+{
+    fn from_bytes_and_meta<'a, 'b>(
+        &self,
+        bytes: &'a [u8],
+        meta: &'b str,
+    ) -> Result<(&'a [u8], &'b str, Listing), nom::Err<Err>> {
+        parse_detached_listing_with_symbols(bytes, meta, self.cfg)
+    }
+    fn describe(&self) -> Option<String> {
+        Some(self.cfg.describe("detached listing with symbols"))
+    }
+}
+
+impl<Err: From<ErrorKind>> ByteMetaWriter<Vec<ListingEntry>, Err> for DetachedParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &Vec<ListingEntry>,
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+    ) -> Result<(), Err> {
+        let image = write_detached_listing(meta, value, self.cfg)?;
+        bytes.extend(image.to_bytes(0));
+        Ok(())
+    }
+}
+
+impl<Err: From<ErrorKind>> ByteMetaWriter<Listing, Err> for DetachedParser {
+    fn to_bytes_and_meta(
+        &self,
+        value: &Listing,
+        bytes: &mut Vec<u8>,
+        meta: &mut String,
+    ) -> Result<(), Err> {
+        let image = write_detached_listing_with_symbols(meta, value, self.cfg)?;
+        bytes.extend(image.to_bytes(0));
+        Ok(())
+    }
 }
 
 /// A nom parser for a potential address token, supporting underscores for inference.
@@ -46,15 +95,45 @@ fn parse_detached_addr<'a, E: nom::error::ParseError<&'a str>>(
     Ok((i, Some(val)))
 }
 
-/// Parse a "detached" listing.
+/// Parse a "detached" listing, up to the next section header.
 pub fn parse_detached_listing<
     'a,
     'b,
-    E: nom::error::ParseError<&'b str> + nom::error::ParseError<&'a [u8]>,
+    E: From<ErrorKind> + nom::error::ParseError<&'b str> + nom::error::ParseError<&'a [u8]>,
+>(
+    raw: &'a [u8],
+    meta: &'b str,
+    cfg: ListingConfig,
+) -> Result<(&'a [u8], &'b str, Vec<ListingEntry>), nom::Err<E>> {
+    parse_detached_lines(raw, meta, cfg, None)
+}
+
+/// Parse a "detached" listing along with its symbols, which are read as in
+/// [`parse_asm_listing_with_symbols`](super::asm::parse_asm_listing_with_symbols).
+pub fn parse_detached_listing_with_symbols<
+    'a,
+    'b,
+    E: From<ErrorKind> + nom::error::ParseError<&'b str> + nom::error::ParseError<&'a [u8]>,
+>(
+    raw: &'a [u8],
+    meta: &'b str,
+    cfg: ListingConfig,
+) -> Result<(&'a [u8], &'b str, Listing), nom::Err<E>> {
+    let mut symbols = SymbolCollector::default();
+    let (raw, meta, entries) = parse_detached_lines(raw, meta, cfg, Some(&mut symbols))?;
+    let symbols = symbols.finish(&entries);
+    Ok((raw, meta, Listing { entries, symbols }))
+}
+
+fn parse_detached_lines<
+    'a,
+    'b,
+    E: From<ErrorKind> + nom::error::ParseError<&'b str> + nom::error::ParseError<&'a [u8]>,
 >(
     mut raw: &'a [u8],
     meta: &'b str,
     cfg: ListingConfig,
+    mut symbols: Option<&mut SymbolCollector>,
 ) -> Result<(&'a [u8], &'b str, Vec<ListingEntry>), nom::Err<E>> {
     let mut out = Vec::new();
     let mut cursor: usize = 0;
@@ -66,6 +145,20 @@ pub fn parse_detached_listing<
             Some((l, r)) => (l, r),
             None => (current_input, ""),
         };
+        // a header, even a malformed one, starts the next section; only its
+        // first line needs looking at
+        if line.starts_with("arch ")
+            || (line.starts_with('.') && !matches!(section::parse_header(current_input), Ok(None)))
+        {
+            break;
+        }
+
+        if let Some(symbols) = symbols.as_deref_mut()
+            && symbols.line(line)
+        {
+            current_input = rest_input;
+            continue;
+        }
 
         if !line.trim().is_empty() {
             let row_res: IResult<&str, (Option<Option<u128>>, &str), E> = (
@@ -79,31 +172,26 @@ pub fn parse_detached_listing<
                     let addr_val = addr_val_opt.unwrap_or(cursor as u128);
                     let offset = addr_val as usize;
 
-                    let group_bits = match cfg.base {
-                        16 => 4 * cfg.entry_group_width,
-                        8 => 3 * cfg.entry_group_width,
-                        _ => 4 * cfg.entry_group_width,
-                    };
-                    let total_bits = group_bits * cfg.entry_groups;
-                    let total_bytes = (total_bits + 7) / 8;
-                    let end = core::cmp::min(raw.len(), offset + total_bytes);
-
-                    let bytes = if offset < raw.len() {
-                        raw[offset..end].to_vec()
-                    } else {
-                        Vec::new()
-                    };
+                    let end = offset
+                        .checked_add(cfg.entry_bytes())
+                        .filter(|&end| end <= raw.len())
+                        .ok_or(nom::Err::Error(E::from(ErrorKind::Truncated)))?;
+                    let bytes = raw[offset..end].to_vec();
 
-                    out.push(ListingEntry {
-                        address: addr_val as u64,
+                    let mut entry = ListingEntry {
+                        address: Some(addr_val as u64),
                         bytes: bytes.clone(),
                         text: String::from(text),
-                    });
+                    };
+                    if let Some(symbols) = symbols.as_deref_mut() {
+                        symbols.entry(&mut entry);
+                    }
+                    out.push(entry);
                     cursor = offset.saturating_add(bytes.len());
                 } else {
                     // No address detected, just text
                     out.push(ListingEntry {
-                        address: 0,
+                        address: None,
                         bytes: Vec::new(),
                         text: String::from(line.trim()),
                     });
@@ -113,58 +201,83 @@ pub fn parse_detached_listing<
         current_input = rest_input;
     }
 
-    Ok((&raw[cursor..], "", out))
+    Ok((&raw[cursor..], current_input, out))
 }
 
-/// Print detached listing comments into a writer and return raw bytes separately.
+/// Print detached listing comments into a writer and collect the entries'
+/// bytes into a [`MemoryImage`], whose file offsets are the entry addresses.
+///
+/// Entries whose bytes disagree where they overlap are reported as
+/// [`ErrorKind::Overlap`].
 pub fn write_detached_listing<W: Write>(
     w: &mut W,
     entries: &[ListingEntry],
     cfg: ListingConfig,
-) -> (core::fmt::Result, Vec<u8>) {
-    let mut raw: Vec<u8> = Vec::new();
-    let mut res = Ok(());
+) -> Result<MemoryImage, ErrorKind> {
+    let mut image = MemoryImage::new();
     for e in entries {
-        if e.address != 0 {
-            if let Err(e) = write_grouped_number(
-                w,
-                e.address as u128,
-                cfg,
-                cfg.addr_groups,
-                cfg.addr_group_width,
-            ) {
-                res = Err(e);
-            }
-            if let Err(e) = w.write_char(' ') {
-                res = Err(e);
-            }
-            if let Err(e) = w.write_str(&e.text) {
-                res = Err(e);
-            }
-            if let Err(e) = w.write_char('\n') {
-                res = Err(e);
-            }
-            // place bytes into raw at the corresponding offset; grow vector if needed
-            let offset = e.address as usize;
-            if raw.len() < offset {
-                raw.resize(offset, 0);
-            }
-            raw.extend_from_slice(&e.bytes);
-        } else {
-            if let Err(e) = w.write_str(&e.text) {
-                res = Err(e);
-            }
-            if let Err(e) = w.write_char('\n') {
-                res = Err(e);
-            }
+        write_detached_entry(w, e, cfg, &mut image)?;
+    }
+    Ok(image)
+}
+
+/// Print a detached listing with its symbols into a writer, as
+/// [`write_asm_listing_with_symbols`](super::asm::write_asm_listing_with_symbols)
+/// does, and collect the entries' bytes into a [`MemoryImage`].
+pub fn write_detached_listing_with_symbols<W: Write>(
+    w: &mut W,
+    listing: &Listing,
+    cfg: ListingConfig,
+) -> Result<MemoryImage, ErrorKind> {
+    listing
+        .symbols
+        .write_definitions(w, &listing.entries, cfg.base)
+        .map_err(|_| ErrorKind::Format)?;
+    let mut image = MemoryImage::new();
+    let mut labelled = BTreeSet::new();
+    for e in &listing.entries {
+        if let Some(address) = e.address
+            && labelled.insert(address)
+        {
+            listing
+                .symbols
+                .write_labels(w, address)
+                .map_err(|_| ErrorKind::Format)?;
         }
+        write_detached_entry(w, e, cfg, &mut image)?;
+    }
+    Ok(image)
+}
+
+fn write_detached_entry<W: Write>(
+    w: &mut W,
+    e: &ListingEntry,
+    cfg: ListingConfig,
+    image: &mut MemoryImage,
+) -> Result<(), ErrorKind> {
+    if let Some(address) = e.address {
+        image.insert(address, &e.bytes)?;
+        write_grouped_number(
+            w,
+            address as u128,
+            cfg,
+            cfg.addr_groups,
+            cfg.addr_group_width,
+        )
+        .map_err(|_| ErrorKind::Format)?;
+        w.write_char(' ').map_err(|_| ErrorKind::Format)?;
     }
-    (res, raw)
+    w.write_str(&e.text).map_err(|_| ErrorKind::Format)?;
+    w.write_char('\n').map_err(|_| ErrorKind::Format)
 }
 
-/// Print detached listing comments and return raw bytes file contents separately.
-pub fn print_detached_listing(entries: &[ListingEntry], cfg: ListingConfig) -> (String, Vec<u8>) {
+/// Print detached listing comments and return raw bytes file contents separately,
+/// with gaps zero-filled.
+pub fn print_detached_listing(
+    entries: &[ListingEntry],
+    cfg: ListingConfig,
+) -> Result<(String, Vec<u8>), ErrorKind> {
     let mut comments = String::new();
-    let (_, raw) = write_detached_listing(&mut comments, entries, cfg);
-    (comments, raw)
+    let image = write_detached_listing(&mut comments, entries, cfg)?;
+    Ok((comments, image.to_bytes(0)))
 }
@@ -1,7 +1,13 @@
 pub mod asm;
+pub mod builtin;
 pub mod core;
 pub mod detached;
+pub mod symbols;
+pub mod word;
 
 pub use asm::*;
+pub use builtin::*;
 pub use core::*;
 pub use detached::*;
+pub use symbols::*;
+pub use word::*;
@@ -0,0 +1,284 @@
+use crate::listing::core::{Base, ListingEntry, parse_literal, write_literal};
+use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
+use core::fmt::{self, Write};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while, take_while_m_n};
+use nom::character::complete::{char as nom_char, space0, space1};
+use nom::combinator::{eof, recognize};
+use nom::sequence::{delimited, terminated};
+use nom::{IResult, Parser};
+
+/// Whether a symbol names a place in the listing or a standalone value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum SymbolKind {
+    /// A `name:` label, whose value is the address of the entry it precedes.
+    Label,
+    /// A `NAME = value` or `NAME EQU value` definition.
+    Constant,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct Symbol {
+    pub value: u64,
+    pub kind: SymbolKind,
+}
+
+/// Symbols defined by a listing, by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: BTreeMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define `name`, returning its previous definition if it had one.
+    pub fn define(&mut self, name: &str, symbol: Symbol) -> Option<Symbol> {
+        self.symbols.insert(String::from(name), symbol)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    /// The value of `name`, if it is defined.
+    pub fn resolve(&self, name: &str) -> Option<u64> {
+        self.get(name).map(|s| s.value)
+    }
+
+    /// Names of the labels at `address`, in name order.
+    pub fn labels_at(&self, address: u64) -> impl Iterator<Item = &str> {
+        self.symbols
+            .iter()
+            .filter(move |(_, s)| s.kind == SymbolKind::Label && s.value == address)
+            .map(|(n, _)| n.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Symbol)> {
+        self.symbols.iter().map(|(n, s)| (n.as_str(), *s))
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Write the definition lines that belong ahead of the entries: every
+    /// constant, plus any label that does not fall on an entry address, which
+    /// is written as a constant since it has no entry to precede.
+    pub fn write_definitions<W: Write>(
+        &self,
+        w: &mut W,
+        entries: &[ListingEntry],
+        base: Base,
+    ) -> fmt::Result {
+        for (name, symbol) in self.iter() {
+            if symbol.kind == SymbolKind::Label
+                && entries.iter().any(|e| e.address == Some(symbol.value))
+            {
+                continue;
+            }
+            write!(w, "{} = ", name)?;
+            write_literal(w, symbol.value, base)?;
+            w.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// Write a `name:` line for each label at `address`.
+    pub fn write_labels<W: Write>(&self, w: &mut W, address: u64) -> fmt::Result {
+        for name in self.labels_at(address) {
+            writeln!(w, "{}:", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// A listing along with the symbols its text defines.
+#[derive(Clone, Debug, Default)]
+pub struct Listing {
+    pub entries: Vec<ListingEntry>,
+    pub symbols: SymbolTable,
+}
+
+/// A line of listing text that defines a symbol.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SymbolLine<'a> {
+    Label(&'a str),
+    Constant(&'a str, u64),
+}
+
+/// A nom parser for a symbol name: a letter, `_` or `.` followed by letters,
+/// digits, `_` or `.`.
+pub fn parse_symbol_name<'a, E: nom::error::ParseError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, &'a str, E> {
+    recognize((
+        take_while_m_n(1, 1, |c: char| {
+            c.is_ascii_alphabetic() || c == '_' || c == '.'
+        }),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+    ))
+    .parse(i)
+}
+
+/// A nom parser for a leading `name:` label, consuming the spaces after it.
+pub fn parse_label<'a, E: nom::error::ParseError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, &'a str, E> {
+    terminated(delimited(space0, parse_symbol_name, nom_char(':')), space0).parse(i)
+}
+
+/// A nom parser for a whole line (without its line ending) that is a label
+/// or a `=`/`EQU` definition.
+pub fn parse_symbol_line<'a, E: nom::error::ParseError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, SymbolLine<'a>, E> {
+    let label = terminated(parse_label, eof).map(SymbolLine::Label);
+    let constant = (
+        delimited(space0, parse_symbol_name, space0),
+        alt((
+            terminated(tag_no_case("equ"), space1),
+            terminated(tag("="), space0),
+        )),
+        terminated(parse_literal, terminated(space0, eof)),
+    )
+        .map(|(name, _, value)| SymbolLine::Constant(name, value));
+    alt((label, constant)).parse(i)
+}
+
+/// Gathers symbols while a listing is parsed line by line, holding labels
+/// on lines of their own until the entry they precede.
+#[derive(Default)]
+pub(crate) struct SymbolCollector {
+    symbols: SymbolTable,
+    pending: Vec<String>,
+}
+
+impl SymbolCollector {
+    /// Take `line` as a symbol line if it is one.
+    pub(crate) fn line(&mut self, line: &str) -> bool {
+        match parse_symbol_line::<()>(line) {
+            Ok((_, SymbolLine::Label(name))) => self.pending.push(String::from(name)),
+            Ok((_, SymbolLine::Constant(name, value))) => {
+                self.symbols.define(
+                    name,
+                    Symbol {
+                        value,
+                        kind: SymbolKind::Constant,
+                    },
+                );
+            }
+            Err(_) => return false,
+        }
+        true
+    }
+
+    /// Define the labels waiting for `entry`, and take a leading label off
+    /// its text.
+    pub(crate) fn entry(&mut self, entry: &mut ListingEntry) {
+        let Some(address) = entry.address else {
+            return;
+        };
+        if let Ok((rest, name)) = parse_label::<()>(&entry.text) {
+            self.pending.push(String::from(name));
+            entry.text = String::from(rest);
+        }
+        self.define_pending(address);
+    }
+
+    /// Finish the table; labels left at the end mark the address just past
+    /// the last entry.
+    pub(crate) fn finish(mut self, entries: &[ListingEntry]) -> SymbolTable {
+        let end = entries
+            .iter()
+            .rev()
+            .find_map(|e| e.address.map(|a| a + e.bytes.len() as u64))
+            .unwrap_or(0);
+        self.define_pending(end);
+        self.symbols
+    }
+
+    fn define_pending(&mut self, address: u64) {
+        for name in self.pending.drain(..) {
+            self.symbols.define(
+                &name,
+                Symbol {
+                    value: address,
+                    kind: SymbolKind::Label,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(i: &str) -> Option<SymbolLine<'_>> {
+        parse_symbol_line::<()>(i).ok().map(|(_, l)| l)
+    }
+
+    #[test]
+    fn symbol_lines() {
+        assert_eq!(line("start:"), Some(SymbolLine::Label("start")));
+        assert_eq!(line("  .loop_2:  "), Some(SymbolLine::Label(".loop_2")));
+        assert_eq!(
+            line("SCREEN = $0400"),
+            Some(SymbolLine::Constant("SCREEN", 0x400))
+        );
+        assert_eq!(line("mask=%1010"), Some(SymbolLine::Constant("mask", 10)));
+        assert_eq!(
+            line("chars equ 0ffh "),
+            Some(SymbolLine::Constant("chars", 0xff))
+        );
+        // not definitions: an instruction after a label, a missing value,
+        // trailing text, a name starting with a digit
+        assert_eq!(line("loop: brk"), None);
+        assert_eq!(line("X ="), None);
+        assert_eq!(line("X = 1 ; one"), None);
+        assert_eq!(line("1x = 1"), None);
+        assert_eq!(line("0000 a9 lda #0"), None);
+    }
+    #[test]
+    fn redefinition() {
+        let mut table = SymbolTable::new();
+        let constant = |value| Symbol {
+            value,
+            kind: SymbolKind::Constant,
+        };
+        assert_eq!(table.define("x", constant(1)), None);
+        assert_eq!(table.define("x", constant(2)), Some(constant(1)));
+
+        // the last definition wins, whether a constant or a label
+        let mut collector = SymbolCollector::default();
+        assert!(collector.line("A = 1"));
+        assert!(collector.line("A = 2"));
+        assert!(collector.line("B = 3"));
+        assert!(collector.line("B:"));
+        assert!(!collector.line("lda #0"));
+        let mut entry = ListingEntry {
+            address: Some(0x10),
+            bytes: alloc::vec![0xea],
+            text: String::from("end: nop"),
+        };
+        collector.entry(&mut entry);
+        assert_eq!(entry.text, "nop");
+        assert!(collector.line("after:"));
+        let table = collector.finish(&[entry]);
+        assert_eq!(table.get("A"), Some(constant(2)));
+        assert_eq!(
+            table.get("B").map(|s| (s.value, s.kind)),
+            Some((0x10, SymbolKind::Label))
+        );
+        assert_eq!(table.resolve("end"), Some(0x10));
+        assert_eq!(table.resolve("after"), Some(0x11));
+        assert_eq!(table.labels_at(0x10).collect::<Vec<_>>(), ["B", "end"]);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::ErrorKind;
+use crate::listing::core::{
+    ByteOrder, EntryLayout, ListingConfig, parse_dotted_groups, parse_spaced_groups,
+    write_grouped_number,
+};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use nom::character::complete::{char as nom_char, not_line_ending, space0, space1};
+use nom::combinator::cut;
+use nom::error::context;
+use nom::multi::many0;
+use nom::sequence::{preceded, terminated};
+use nom::{IResult, Parser};
+
+/// A listing entry of whole machine words of `ListingConfig::word_bits` bits,
+/// for machines such as the 12-bit PDP-8 or 18-bit PDP-1 whose words are not
+/// a whole number of bytes.
+#[derive(Clone, Debug)]
+pub struct WordEntry {
+    pub address: u64,
+    pub words: Vec<u64>,
+    pub text: String,
+}
+
+/// How words are stored in a byte stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WordPacking {
+    /// Each word in the fewest whole bytes that hold it, e.g. two bytes per
+    /// 12-bit word, in the given byte order.
+    Padded(ByteOrder),
+    /// Words as one continuous big-endian bit stream, e.g. two 12-bit words
+    /// in three bytes; a partial final byte is zero-filled.
+    Packed,
+}
+
+/// PDP-8 paper-tape framings, which carry addresses along with the words.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tape {
+    /// Read-in mode loader format: every word is preceded by its address.
+    Rim,
+    /// Binary loader format: addresses only where they change, followed by
+    /// a checksum.
+    Bin,
+}
+
+/// Widest word the word functions take, the size of the `u64` holding it.
+const MAX_WORD_BITS: usize = 64;
+
+/// Fail with [`ErrorKind::Format`] unless `bits` is a usable word size.
+fn check_word_bits(bits: usize) -> Result<(), ErrorKind> {
+    if bits == 0 || bits > MAX_WORD_BITS {
+        return Err(ErrorKind::Format);
+    }
+    Ok(())
+}
+
+/// Mask of the low `bits` bits, which must have passed [`check_word_bits`].
+fn word_mask(bits: usize) -> u64 {
+    u64::MAX >> (MAX_WORD_BITS - bits)
+}
+
+/// Split an entry value into `count` words of `bits` bits, most significant first.
+fn split_words(value: u128, bits: usize, count: usize) -> Vec<u64> {
+    (0..count)
+        .rev()
+        .map(|i| (value.checked_shr((i * bits) as u32).unwrap_or(0) as u64) & word_mask(bits))
+        .collect()
+}
+
+/// A nom parser for a single line of a word listing.
+fn parse_word_line<'a, E: nom::error::ParseError<&'a str> + nom::error::ContextError<&'a str>>(
+    i: &'a str,
+    cfg: ListingConfig,
+) -> IResult<&'a str, WordEntry, E> {
+    let addr_p = |i| parse_dotted_groups(i, cfg.base, cfg.addr_groups);
+    let entry_p = |i| match cfg.entry_layout {
+        EntryLayout::Fixed => {
+            let (i, value) = parse_dotted_groups(i, cfg.base, cfg.entry_groups)?;
+            let bits = cfg
+                .base
+                .bits_for_digits(cfg.entry_group_width * cfg.entry_groups);
+            let count = bits.div_ceil(cfg.word_bits).max(1);
+            Ok((i, split_words(value, cfg.word_bits, count)))
+        }
+        EntryLayout::Bytes => {
+            let (i, groups) = parse_spaced_groups(i, cfg, word_mask(cfg.word_bits) as u128)?;
+            Ok((i, groups.into_iter().map(|w| w as u64).collect()))
+        }
+    };
+
+    let (i, (_, addr_val, _, words, rest)) = (
+        space0,
+        addr_p,
+        space1,
+        cut(context("word listing entry", entry_p)),
+        preceded(space0, not_line_ending),
+    )
+        .parse(i)?;
+
+    Ok((
+        i,
+        WordEntry {
+            address: addr_val as u64,
+            words,
+            text: String::from(rest),
+        },
+    ))
+}
+
+/// Parse a listing of `cfg.word_bits`-bit words. With [`EntryLayout::Bytes`]
+/// each space-separated group is one word.
+///
+/// Fails with [`ErrorKind::Format`] unless `cfg.word_bits` is between 1 and 64.
+pub fn parse_word_listing<
+    'a,
+    E: nom::error::ParseError<&'a str>
+        + nom::error::ContextError<&'a str>
+        + nom::error::FromExternalError<&'a str, ErrorKind>,
+>(
+    text: &'a str,
+    cfg: ListingConfig,
+) -> IResult<&'a str, Vec<WordEntry>, E> {
+    if let Err(e) = check_word_bits(cfg.word_bits) {
+        return Err(nom::Err::Failure(E::from_external_error(
+            text,
+            nom::error::ErrorKind::Verify,
+            e,
+        )));
+    }
+    many0(terminated(|i| parse_word_line(i, cfg), nom_char('\n'))).parse(text)
+}
+
+/// Print word entries as listing lines according to `cfg` into the provided
+/// writer. `cfg.word_bits` must be between 1 and 64.
+pub fn write_word_listing<W: Write>(
+    w: &mut W,
+    entries: &[WordEntry],
+    cfg: ListingConfig,
+) -> core::fmt::Result {
+    for e in entries {
+        write_grouped_number(
+            w,
+            e.address as u128,
+            cfg,
+            cfg.addr_groups,
+            cfg.addr_group_width,
+        )?;
+        w.write_char(' ')?;
+        match cfg.entry_layout {
+            EntryLayout::Fixed => {
+                let mut val: u128 = 0;
+                for &word in &e.words {
+                    val = (val << cfg.word_bits) | (word & word_mask(cfg.word_bits)) as u128;
+                }
+                write_grouped_number(w, val, cfg, cfg.entry_groups, cfg.entry_group_width)?;
+            }
+            EntryLayout::Bytes => {
+                for (i, &word) in e.words.iter().enumerate() {
+                    if i > 0 {
+                        w.write_char(' ')?;
+                    }
+                    write_grouped_number(w, word as u128, cfg, 1, cfg.entry_group_width)?;
+                }
+            }
+        }
+        if !e.text.is_empty() {
+            w.write_char(' ')?;
+            w.write_str(&e.text)?;
+        }
+        w.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Print word entries as listing lines according to `cfg`.
+pub fn print_word_listing(entries: &[WordEntry], cfg: ListingConfig) -> String {
+    let mut s = String::new();
+    write_word_listing(&mut s, entries, cfg).unwrap();
+    s
+}
+
+/// Store `bits`-bit words into bytes. Fails with [`ErrorKind::Format`]
+/// unless `bits` is between 1 and 64.
+pub fn pack_words(words: &[u64], bits: usize, packing: WordPacking) -> Result<Vec<u8>, ErrorKind> {
+    check_word_bits(bits)?;
+    let mut out = Vec::new();
+    match packing {
+        WordPacking::Padded(order) => {
+            let n = bits.div_ceil(8);
+            for &word in words {
+                let word = word & word_mask(bits);
+                let start = out.len();
+                out.extend((0..n).rev().map(|i| (word >> (8 * i)) as u8));
+                if order == ByteOrder::Little {
+                    out[start..].reverse();
+                }
+            }
+        }
+        WordPacking::Packed => {
+            let mut acc: u128 = 0;
+            let mut held = 0;
+            for &word in words {
+                acc = (acc << bits) | (word & word_mask(bits)) as u128;
+                held += bits;
+                while held >= 8 {
+                    held -= 8;
+                    out.push((acc >> held) as u8);
+                }
+                acc &= (1u128 << held) - 1;
+            }
+            if held > 0 {
+                out.push((acc << (8 - held)) as u8);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Read `bits`-bit words back from bytes stored with `packing`. Trailing
+/// bytes that do not make up a whole word are ignored. Fails with
+/// [`ErrorKind::Format`] unless `bits` is between 1 and 64.
+pub fn unpack_words(
+    bytes: &[u8],
+    bits: usize,
+    packing: WordPacking,
+) -> Result<Vec<u64>, ErrorKind> {
+    check_word_bits(bits)?;
+    let mut out = Vec::new();
+    match packing {
+        WordPacking::Padded(order) => {
+            for chunk in bytes.chunks_exact(bits.div_ceil(8)) {
+                let mut word: u64 = 0;
+                let mut push = |b: &u8| word = (word << 8) | *b as u64;
+                match order {
+                    ByteOrder::Big => chunk.iter().for_each(&mut push),
+                    ByteOrder::Little => chunk.iter().rev().for_each(&mut push),
+                }
+                out.push(word & word_mask(bits));
+            }
+        }
+        WordPacking::Packed => {
+            let mut acc: u128 = 0;
+            let mut held = 0;
+            for &b in bytes {
+                acc = (acc << 8) | b as u128;
+                held += 8;
+                if held >= bits {
+                    held -= bits;
+                    out.push((acc >> held) as u64 & word_mask(bits));
+                    acc &= (1u128 << held) - 1;
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Leader/trailer frame: channel 8 punched.
+const TAPE_LEADER: u8 = 0o200;
+/// Origin marker: channel 7 punched.
+const TAPE_ORIGIN: u8 = 0o100;
+const TAPE_LEADER_LEN: usize = 16;
+
+/// Largest word or address a tape frame pair holds.
+const TAPE_WORD_MAX: u64 = 0o7777;
+
+/// Punch 12-bit word entries onto paper tape in the given framing. Fails
+/// with [`ErrorKind::Format`] if a word or address does not fit in 12 bits.
+pub fn write_tape(entries: &[WordEntry], tape: Tape) -> Result<Vec<u8>, ErrorKind> {
+    let mut out = alloc::vec![TAPE_LEADER; TAPE_LEADER_LEN];
+    let mut sum: u16 = 0;
+    let mut next: Option<u64> = None;
+    for e in entries {
+        for (i, &word) in e.words.iter().enumerate() {
+            let addr = e.address.saturating_add(i as u64);
+            if addr > TAPE_WORD_MAX || word > TAPE_WORD_MAX {
+                return Err(ErrorKind::Format);
+            }
+            let mut frames = Vec::new();
+            if tape == Tape::Rim || next != Some(addr) {
+                frames.push(TAPE_ORIGIN | ((addr >> 6) & 0o77) as u8);
+                frames.push((addr & 0o77) as u8);
+            }
+            frames.push(((word >> 6) & 0o77) as u8);
+            frames.push((word & 0o77) as u8);
+            for &f in &frames {
+                sum = sum.wrapping_add(f as u16);
+            }
+            out.extend(frames);
+            next = Some(addr + 1);
+        }
+    }
+    if tape == Tape::Bin {
+        out.push(((sum >> 6) & 0o77) as u8);
+        out.push((sum & 0o77) as u8);
+    }
+    out.extend([TAPE_LEADER; TAPE_LEADER_LEN]);
+    Ok(out)
+}
+
+/// Read 12-bit word entries from paper tape in the given framing, merging
+/// consecutive addresses into one entry. Field-setting frames are skipped.
+pub fn read_tape(bytes: &[u8], tape: Tape) -> Result<Vec<WordEntry>, ErrorKind> {
+    let mut frames = bytes
+        .iter()
+        .copied()
+        .skip_while(|&f| f == TAPE_LEADER)
+        .take_while(|&f| f != TAPE_LEADER)
+        .filter(|&f| f & 0o300 != 0o300);
+    let mut pairs = Vec::new();
+    while let Some(hi) = frames.next() {
+        let lo = frames.next().ok_or(ErrorKind::Truncated)?;
+        pairs.push((hi, lo));
+    }
+    if tape == Tape::Bin {
+        let (hi, lo) = pairs.pop().ok_or(ErrorKind::Truncated)?;
+        let sum = pairs.iter().fold(0u16, |s, &(h, l)| {
+            s.wrapping_add(h as u16).wrapping_add(l as u16)
+        }) & TAPE_WORD_MAX as u16;
+        if (hi as u16 & 0o77) << 6 | (lo as u16 & 0o77) != sum {
+            return Err(ErrorKind::Checksum);
+        }
+    }
+    let mut out: Vec<WordEntry> = Vec::new();
+    let mut addr: Option<u64> = None;
+    for (hi, lo) in pairs {
+        let value = ((hi as u64 & 0o77) << 6) | (lo as u64 & 0o77);
+        if hi & TAPE_ORIGIN != 0 {
+            addr = Some(value);
+            continue;
+        }
+        let Some(a) = addr else {
+            return Err(ErrorKind::Truncated);
+        };
+        match out.last_mut() {
+            Some(e) if e.address + e.words.len() as u64 == a => e.words.push(value),
+            _ => out.push(WordEntry {
+                address: a,
+                words: alloc::vec![value],
+                text: String::new(),
+            }),
+        }
+        addr = match tape {
+            Tape::Rim => None,
+            Tape::Bin => Some(a + 1),
+        };
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+
+    #[test]
+    fn pdp8_round_trip() {
+        let cfg = ListingConfig::new_octal(1, 4, 1, 4)
+            .with_word_bits(12)
+            .unwrap();
+        let text = "0200 7300 CLA CLL\n0201 1205 TAD\n";
+        let (rest, entries) = parse_word_listing::<Diagnostic>(text, cfg).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(entries[0].words, [0o7300]);
+        assert_eq!(print_word_listing(&entries, cfg), text);
+        for tape in [Tape::Rim, Tape::Bin] {
+            let back = read_tape(&write_tape(&entries, tape).unwrap(), tape).unwrap();
+            assert_eq!(back.len(), 1);
+            assert_eq!(back[0].address, 0o200);
+            assert_eq!(back[0].words, [0o7300, 0o1205]);
+        }
+    }
+    #[test]
+    fn packing_round_trip() {
+        let words = [0xabc, 0x123, 0xfff];
+        let packed = pack_words(&words, 12, WordPacking::Packed).unwrap();
+        assert_eq!(packed, [0xab, 0xc1, 0x23, 0xff, 0xf0]);
+        assert_eq!(
+            unpack_words(&packed, 12, WordPacking::Packed).unwrap()[..3],
+            words
+        );
+        let le = WordPacking::Padded(ByteOrder::Little);
+        let padded = pack_words(&words, 12, le).unwrap();
+        assert_eq!(unpack_words(&padded, 12, le).unwrap(), words);
+        let wide = [u64::MAX, 1];
+        let packed = pack_words(&wide, 64, WordPacking::Packed).unwrap();
+        assert_eq!(
+            unpack_words(&packed, 64, WordPacking::Packed).unwrap(),
+            wide
+        );
+    }
+    #[test]
+    fn zero_bit_words() {
+        let mut cfg = ListingConfig::new_octal(1, 4, 1, 4);
+        cfg.word_bits = 0;
+        match parse_word_listing::<Diagnostic>("0200 7300\n", cfg) {
+            Err(nom::Err::Failure(d)) => assert_eq!(d.kind, ErrorKind::Format),
+            other => panic!("{other:?}"),
+        }
+    }
+    #[test]
+    fn bad_word_widths() {
+        for bits in [0, 65] {
+            for packing in [WordPacking::Packed, WordPacking::Padded(ByteOrder::Big)] {
+                assert_eq!(
+                    pack_words(&[1], bits, packing).unwrap_err(),
+                    ErrorKind::Format
+                );
+                assert_eq!(
+                    unpack_words(&[1, 2], bits, packing).unwrap_err(),
+                    ErrorKind::Format
+                );
+            }
+        }
+    }
+    #[test]
+    fn tape_overflow() {
+        let entry = |address, word| WordEntry {
+            address,
+            words: alloc::vec![word],
+            text: String::new(),
+        };
+        for tape in [Tape::Rim, Tape::Bin] {
+            assert!(write_tape(&[entry(0o7777, 0o7777)], tape).is_ok());
+            assert_eq!(
+                write_tape(&[entry(0o200, 0o10000)], tape).unwrap_err(),
+                ErrorKind::Format
+            );
+            assert_eq!(
+                write_tape(&[entry(0o10000, 0)], tape).unwrap_err(),
+                ErrorKind::Format
+            );
+            assert_eq!(
+                write_tape(&[entry(u64::MAX, 0)], tape).unwrap_err(),
+                ErrorKind::Format
+            );
+        }
+    }
+    #[test]
+    fn bad_tape_checksum() {
+        let cfg = ListingConfig::new_octal(1, 4, 1, 4)
+            .with_word_bits(12)
+            .unwrap();
+        let (_, entries) = parse_word_listing::<Diagnostic>("0200 7300\n", cfg).unwrap();
+        let mut tape = write_tape(&entries, Tape::Bin).unwrap();
+        tape[TAPE_LEADER_LEN + 2] ^= 1;
+        assert_eq!(
+            read_tape(&tape, Tape::Bin).unwrap_err(),
+            ErrorKind::Checksum
+        );
+        assert_eq!(
+            read_tape(&tape[..TAPE_LEADER_LEN + 1], Tape::Bin).unwrap_err(),
+            ErrorKind::Truncated
+        );
+    }
+}
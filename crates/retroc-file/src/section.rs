@@ -0,0 +1,160 @@
+use crate::ErrorKind;
+use crate::listing::core::{Base, parse_literal, write_literal};
+use crate::listing::symbols::parse_symbol_name;
+use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
+use core::fmt::Write;
+use nom::Parser;
+use nom::combinator::all_consuming;
+
+/// The header of one section of a retroc file.
+///
+/// A header is a run of `.key value` directive lines ending with the
+/// `arch <name>` line, e.g.
+///
+/// ```text
+/// .name bank3
+/// .bank 3
+/// .org $8000
+/// arch asm-hex-16
+/// ```
+///
+/// `org`, `entry` and `bank` take numeric literals; `name` takes the rest of
+/// its line. Any other key is kept in `attrs`. The leading `.` keeps
+/// directives apart from the listing lines of the section before.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Section {
+    pub arch: String,
+    pub name: Option<String>,
+    /// Load address.
+    pub org: Option<u64>,
+    /// Start address.
+    pub entry: Option<u64>,
+    pub bank: Option<u64>,
+    pub attrs: BTreeMap<String, String>,
+}
+
+impl Section {
+    /// A section with no directives besides its `arch`.
+    pub fn new(arch: &str) -> Self {
+        Self {
+            arch: String::from(arch),
+            ..Self::default()
+        }
+    }
+
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(String::as_str)
+    }
+
+    /// Write the header lines, ending with the `arch` line.
+    pub fn write_header<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(w, ".name {}", name)?;
+        }
+        for (key, value) in [
+            ("org", self.org),
+            ("entry", self.entry),
+            ("bank", self.bank),
+        ] {
+            if let Some(value) = value {
+                write!(w, ".{} ", key)?;
+                write_literal(w, value, Base::Hex)?;
+                w.write_char('\n')?;
+            }
+        }
+        for (key, value) in &self.attrs {
+            writeln!(w, ".{} {}", key, value)?;
+        }
+        writeln!(w, "arch {}", self.arch)
+    }
+}
+
+type HeaderError<'a> = (ErrorKind, &'a str, &'a str);
+
+/// Read a section header off the front of `meta`.
+///
+/// Returns `Ok(None)` if `meta` does not start with a header, that is with
+/// `.key value` lines followed by an `arch` line, so that whatever follows
+/// the last section is left alone. Errors carry the
+/// metadata at the offending line and the section's `arch`, as far as it
+/// was read.
+pub(crate) fn parse_header(meta: &str) -> Result<Option<(&str, Section)>, HeaderError<'_>> {
+    let mut directives = Vec::new();
+    let mut rest = meta;
+    let arch;
+    loop {
+        let Some((line, next)) = rest.split_once('\n') else {
+            if let Some(arch) = rest.strip_prefix("arch ") {
+                return Err((ErrorKind::ArchIncomplete, rest, arch));
+            }
+            return Ok(None);
+        };
+        if let Some(a) = line.strip_prefix("arch ") {
+            arch = a;
+            rest = next;
+            break;
+        }
+        let Some((key, value)) = line.strip_prefix('.').and_then(|l| l.split_once(' ')) else {
+            return Ok(None);
+        };
+        if all_consuming(parse_symbol_name::<()>).parse(key).is_err() {
+            return Ok(None);
+        }
+        directives.push((rest, key, value.trim()));
+        rest = next;
+    }
+
+    let mut section = Section::new(arch);
+    for (at, key, value) in directives {
+        let literal = || {
+            all_consuming(parse_literal::<()>)
+                .parse(value)
+                .map(|(_, v)| Some(v))
+                .map_err(|_| (ErrorKind::Directive, at, arch))
+        };
+        match key {
+            "name" => section.name = Some(String::from(value)),
+            "org" => section.org = literal()?,
+            "entry" => section.entry = literal()?,
+            "bank" => section.bank = literal()?,
+            _ => {
+                section.attrs.insert(String::from(key), String::from(value));
+            }
+        }
+    }
+    Ok(Some((rest, section)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let meta = ".name main\n.org $8000\n.entry 0x8002\n.mapper mmc1\narch asm\n8000 a9\n";
+        let (rest, section) = parse_header(meta).unwrap().unwrap();
+        assert_eq!(rest, "8000 a9\n");
+        assert_eq!(section.name.as_deref(), Some("main"));
+        assert_eq!((section.org, section.entry), (Some(0x8000), Some(0x8002)));
+        assert_eq!(section.attr("mapper"), Some("mmc1"));
+        let mut written = String::new();
+        section.write_header(&mut written).unwrap();
+        assert_eq!(parse_header(&written).unwrap().unwrap(), ("", section));
+    }
+    #[test]
+    fn listing_lines_are_not_directives() {
+        // `key value` shaped lines of the previous section end the header
+        for meta in ["c000 rts\narch asm\n", "reset vector\n.org 0\narch asm\n"] {
+            assert_eq!(parse_header(meta), Ok(None));
+        }
+    }
+    #[test]
+    fn malformed_header() {
+        let meta = ".org nope\narch asm\n";
+        assert_eq!(parse_header(meta), Err((ErrorKind::Directive, meta, "asm")));
+        assert_eq!(
+            parse_header(".bank 1\narch asm"),
+            Err((ErrorKind::ArchIncomplete, "arch asm", "asm"))
+        );
+    }
+}